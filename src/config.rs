@@ -1,15 +1,93 @@
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use crate::Float;
+
 const DEFAULT_MAX_BINS: u32 = 2048;
 const DEFAULT_ALPHA: f64 = 0.01;
 const DEFAULT_MIN_VALUE: f64 = 1.0e-9;
 
+// The Datadog Agent grows bins by 1 + 2 * eps rather than deriving gamma from alpha
+const AGENT_EPS: f64 = 1.0 / 128.0;
+const AGENT_MAX_BINS: u32 = 4096;
+const AGENT_MIN_VALUE: f64 = 1.0e-9;
+
+/// How a `Config` was constructed, as reported by `Config::provenance`. This is purely
+/// diagnostic, and configs that differ only in provenance are equal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Provenance {
+    /// Built by `Config::defaults`
+    Default,
+    /// Built by `Config::agent_defaults`
+    AgentDefault,
+    /// Built by `Config::new`, or decoded from a serialized sketch
+    Custom,
+    /// Built by `Config::from_gamma` or `Config::agent_with_alpha`
+    FromGamma,
+}
+
+/// Which end of the key range the store gives up resolution at once it reaches `max_num_bins`,
+/// as set by `Config::with_collapse_strategy`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CollapseStrategy {
+    /// Fold the lowest bins into one, keeping full resolution for the highest values. This is
+    /// the default, and suits latency data where the tail matters most.
+    CollapseLowest,
+    /// Fold the highest bins into one, keeping full resolution for the lowest values
+    CollapseHighest,
+}
+
 /// The configuration struct for constructing a `DDSketch`
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        try_from = "crate::serde_support::ConfigRepr",
+        into = "crate::serde_support::ConfigRepr"
+    )
+)]
 pub struct Config {
     pub max_num_bins: u32,
     pub gamma: f64,
-    gamma_ln: f64,
-    min_value: f64,
+    pub(crate) gamma_ln: f64,
+    pub(crate) min_value: f64,
     pub offset: i32,
+    pub(crate) max_value: f64,
+    pub(crate) collapse: CollapseStrategy,
+    pub(crate) log_centered: bool,
+    pub(crate) provenance: Provenance,
+}
+
+// Compares everything that affects how samples are binned, ignoring the provenance
+impl PartialEq for Config {
+    fn eq(&self, o: &Config) -> bool {
+        self.max_num_bins == o.max_num_bins
+            && self.gamma == o.gamma
+            && self.gamma_ln == o.gamma_ln
+            && self.min_value == o.min_value
+            && self.offset == o.offset
+            && self.max_value == o.max_value
+            && self.collapse == o.collapse
+            && self.log_centered == o.log_centered
+    }
+}
+
+/// The built-in default settings, as returned by `Config::defaults`
+impl Default for Config {
+    fn default() -> Self {
+        Config::defaults()
+    }
+}
+
+// The store indexes its bins with i32, so a larger limit would wrap to a negative capacity
+fn check_max_num_bins(max_num_bins: u32) {
+    assert!(
+        max_num_bins <= i32::MAX as u32,
+        "max_num_bins must not exceed i32::MAX, got {}",
+        max_num_bins
+    );
 }
 
 fn log_gamma(value: f64, gamma_ln: f64) -> f64 {
@@ -21,7 +99,10 @@ impl Config {
     /// configure this, the `defaults` method constructs a `Config` with built-in defaults.
     ///
     /// `max_num_bins` is the max number of bins the DDSketch will grow to, in steps of 128 bins.
+    /// Panics if it exceeds `i32::MAX`, the most the store can index; `Config::builder` reports
+    /// this as an error instead.
     pub fn new(alpha: f64, max_num_bins: u32, min_value: f64) -> Self {
+        check_max_num_bins(max_num_bins);
         let gamma_ln = (2.0 * alpha) / (1.0 - alpha);
         let gamma_ln = gamma_ln.ln_1p();
 
@@ -31,29 +112,589 @@ impl Config {
             gamma_ln,
             min_value,
             offset: 1 - (log_gamma(min_value, gamma_ln) as i32),
+            max_value: f64::INFINITY,
+            collapse: CollapseStrategy::CollapseLowest,
+            log_centered: false,
+            provenance: Provenance::Custom,
+        }
+    }
+
+    /// Construct a new `Config` from the bin growth factor `gamma` rather than the relative
+    /// accuracy, as handed out by other DDSketch implementations. This avoids losing precision
+    /// by converting `gamma` to alpha and back. Panics if `max_num_bins` exceeds `i32::MAX`.
+    pub fn from_gamma(gamma: f64, max_num_bins: u32, min_value: f64) -> Self {
+        check_max_num_bins(max_num_bins);
+        let gamma_ln = gamma.ln();
+
+        Config {
+            max_num_bins,
+            gamma,
+            gamma_ln,
+            min_value,
+            offset: 1 - (log_gamma(min_value, gamma_ln) as i32),
+            max_value: f64::INFINITY,
+            collapse: CollapseStrategy::CollapseLowest,
+            log_centered: false,
+            provenance: Provenance::FromGamma,
         }
     }
 
     /// Return a `Config` using built-in default settings
     pub fn defaults() -> Self {
-        Self::new(DEFAULT_ALPHA, DEFAULT_MAX_BINS, DEFAULT_MIN_VALUE)
+        Config {
+            provenance: Provenance::Default,
+            ..Self::new(DEFAULT_ALPHA, DEFAULT_MAX_BINS, DEFAULT_MIN_VALUE)
+        }
+    }
+
+    /// Return a `ConfigBuilder` starting from the built-in defaults, which names each parameter
+    /// and validates them, unlike `new`
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
     }
 
-    pub fn key(self: &Self, v: f64) -> i32 {
+    /// Return a `Config` matching the accuracy, bin limit and minimum value used by the Datadog
+    /// Agent, with a relative accuracy of 1/128
+    pub fn agent_defaults() -> Self {
+        Config {
+            provenance: Provenance::AgentDefault,
+            ..Self::agent_with_alpha(AGENT_EPS)
+        }
+    }
+
+    /// Return a `Config` with the Datadog Agent's bin limit and minimum value, and its way of
+    /// deriving the bin growth factor as `1 + 2 * alpha`, but a caller-specified accuracy.
+    /// `agent_with_alpha(1.0 / 128.0)` is equal to `agent_defaults`.
+    ///
+    /// Keys follow the same index formula as the Agent, but with a different gamma they are
+    /// only meaningful to consumers that read gamma from the encoded sketch rather than assuming
+    /// the Agent's own. Bins from sketches with different alphas can not be merged.
+    pub fn agent_with_alpha(alpha: f64) -> Self {
+        Self::from_gamma(1.0 + 2.0 * alpha, AGENT_MAX_BINS, AGENT_MIN_VALUE)
+    }
+
+    /// Return a `Config` for multiplicative data such as ratios and speedups, whose bins are
+    /// laid out symmetrically in log space around 1.0: a value and its reciprocal map to keys of
+    /// opposite sign, so a speedup of 2x and a slowdown to 0.5x are resolved alike. The domain
+    /// is strictly positive; zero and negative samples are rejected by `DDSketch::add` and
+    /// reported by `DDSketch::out_of_range_count`, and there is no zero bin. Positive values
+    /// below the default minimum value share the lowest bin.
+    ///
+    /// Sketches built from this config can only be merged with others that are log-centered.
+    pub fn log_centered(alpha: f64, max_num_bins: u32) -> Self {
+        Config {
+            log_centered: true,
+            ..Self::new(alpha, max_num_bins, DEFAULT_MIN_VALUE)
+        }
+    }
+
+    /// Returns true if this config was built by `log_centered`
+    pub fn is_log_centered(&self) -> bool {
+        self.log_centered
+    }
+
+    /// Return a copy of this `Config` where samples whose magnitude exceeds `max_value` are
+    /// rejected by `DDSketch::add` rather than stretching the store, and reported by
+    /// `DDSketch::out_of_range_count` instead. By default there is no upper bound.
+    pub fn with_max_value(&self, max_value: f64) -> Config {
+        Config { max_value, ..*self }
+    }
+
+    /// Returns the largest magnitude accepted by `DDSketch::add`
+    pub fn max_value(&self) -> f64 {
+        self.max_value
+    }
+
+    /// Return a copy of this `Config` whose sketches collapse bins at the end of the key range
+    /// given by `collapse` when they reach `max_num_bins`. Sketches with different strategies
+    /// can not be merged.
+    pub fn with_collapse_strategy(&self, collapse: CollapseStrategy) -> Config {
+        Config { collapse, ..*self }
+    }
+
+    /// Returns which end of the key range bins are collapsed at
+    pub fn collapse_strategy(&self) -> CollapseStrategy {
+        self.collapse
+    }
+
+    /// Returns how this config was constructed. Two configs with different provenance may
+    /// still be equal, as `defaults` and `new(0.01, 2048, 1.0e-9)` are.
+    pub fn provenance(&self) -> Provenance {
+        self.provenance
+    }
+
+    /// Return a short label naming the well-known preset this config is equal to, being
+    /// `"library-default"` for `defaults`, `"agent-default"` for `agent_defaults` and
+    /// `"custom"` otherwise. Useful for explaining in logs why two sketches can not be merged.
+    pub fn describe(&self) -> &'static str {
+        if *self == Self::defaults() {
+            "library-default"
+        } else if *self == Self::agent_defaults() {
+            "agent-default"
+        } else {
+            "custom"
+        }
+    }
+
+    /// Return a `Config` with the same bin limit and minimum value, but with alpha snapped to
+    /// the closest of the `allowed` values. This helps keep sketches built from slightly
+    /// different settings mergeable. If `allowed` is empty the config is returned unchanged.
+    pub fn snap_alpha(&self, allowed: &[f64]) -> Config {
+        let alpha = self.alpha();
+        let closest = allowed
+            .iter()
+            .copied()
+            .filter(|a| !a.is_nan())
+            .min_by(|a, b| (a - alpha).abs().partial_cmp(&(b - alpha).abs()).unwrap());
+
+        match closest {
+            Some(a) => Config {
+                collapse: self.collapse,
+                log_centered: self.log_centered,
+                ..Config::new(a, self.max_num_bins, self.min_value).with_max_value(self.max_value)
+            },
+            None => *self,
+        }
+    }
+
+    /// Returns true if this config resolves values strictly more finely than `other`: its bins
+    /// grow by a smaller gamma, and it separates values from zero at least as closely, with a
+    /// `min_value` no larger than `other`'s. The bin limit is not considered. When choosing a
+    /// merge or downsampling target, the coarser config is the one that is not finer.
+    pub fn is_finer_than(&self, other: &Config) -> bool {
+        self.gamma < other.gamma && self.min_value <= other.min_value
+    }
+
+    // The bin limit as the store takes it. The public field may have been set past what
+    // `new` accepts, so this is checked again.
+    pub(crate) fn bin_limit(&self) -> i32 {
+        check_max_num_bins(self.max_num_bins);
+        self.max_num_bins as i32
+    }
+
+    // Whether the parameters describe a usable mapping, for validating decoded configs. The
+    // store takes the bin limit as an i32.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.max_num_bins > 0
+            && self.max_num_bins <= i32::MAX as u32
+            && self.gamma.is_finite()
+            && self.gamma > 1.0
+            && self.gamma_ln.is_finite()
+            && self.gamma_ln > 0.0
+            && self.min_value.is_finite()
+            && self.min_value > 0.0
+            && !self.max_value.is_nan()
+    }
+
+    // Whether both configs map values to the same bin keys, ignoring the bin limit
+    pub(crate) fn same_mapping(&self, o: &Config) -> bool {
+        self.gamma == o.gamma
+            && self.gamma_ln == o.gamma_ln
+            && self.min_value == o.min_value
+            && self.offset == o.offset
+            && self.log_centered == o.log_centered
+    }
+
+    /// Returns the relative accuracy guaranteed by the config, recovered from `gamma`. For a
+    /// config built with `new` this is the alpha it was given, up to rounding.
+    pub fn alpha(&self) -> f64 {
+        (self.gamma - 1.0) / (self.gamma + 1.0)
+    }
+
+    /// Returns the factor by which the width of each bin grows over the one before
+    pub fn gamma(&self) -> f64 {
+        self.gamma
+    }
+
+    /// Returns the max number of bins the DDSketch will grow to
+    pub fn max_num_bins(&self) -> u32 {
+        self.max_num_bins
+    }
+
+    /// Returns the magnitude below which values are mapped to the zero bin
+    pub fn min_value(&self) -> f64 {
+        self.min_value
+    }
+
+    pub fn key(&self, v: f64) -> i32 {
+        if self.log_centered {
+            return self.log_gamma(v.max(self.min_value)).round() as i32;
+        }
+
         if v < -self.min_value {
-            return -(self.log_gamma(-v).ceil() as i32) - self.offset;
+            -(self.log_gamma(-v).ceil() as i32) - self.offset
         } else if v > self.min_value {
-            return (self.log_gamma(v).ceil() as i32) + self.offset;
+            (self.log_gamma(v).ceil() as i32) + self.offset
         } else {
-            return 0;
+            0
         }
     }
 
-    pub fn log_gamma(self: &Self, value: f64) -> f64 {
+    // Returns true if a finite value can be recorded: no larger in magnitude than `max_value`,
+    // and positive for a log-centered config, which has no bins for zero or negative values
+    pub(crate) fn in_range(&self, v: f64) -> bool {
+        v.abs() <= self.max_value && (!self.log_centered || v > 0.0)
+    }
+
+    // The largest magnitude of the key of any finite value, for rejecting decoded bins that no
+    // sample could have produced
+    pub(crate) fn max_key_magnitude(&self) -> u32 {
+        self.key(f64::MAX)
+            .unsigned_abs()
+            .max(self.key(-f64::MAX).unsigned_abs())
+    }
+
+    /// Return the value represented by a bin key, which is within the relative accuracy of
+    /// every value mapped to that key. Key 0 represents all values within `min_value` of zero,
+    /// or those closest to 1.0 for a log-centered config.
+    pub fn value(&self, key: i32) -> f64 {
+        if self.log_centered {
+            return 2.0 * self.pow_gamma_half(key, 1) / (1.0 + self.gamma);
+        }
+
+        if key < 0 {
+            -2.0 * self.pow_gamma(-(key + self.offset)) / (1.0 + self.gamma)
+        } else if key > 0 {
+            2.0 * self.pow_gamma(key - self.offset) / (1.0 + self.gamma)
+        } else {
+            0.0
+        }
+    }
+
+    /// Return the lowest value mapped to a bin key
+    pub fn lower_bound(&self, key: i32) -> f64 {
+        if self.log_centered {
+            return self.pow_gamma_half(key, -1);
+        }
+
+        if key < 0 {
+            -self.pow_gamma(-key - self.offset)
+        } else if key > 0 {
+            self.pow_gamma(key - self.offset - 1)
+        } else {
+            -self.min_value
+        }
+    }
+
+    /// Return the highest value mapped to a bin key
+    pub fn upper_bound(&self, key: i32) -> f64 {
+        if self.log_centered {
+            return self.pow_gamma_half(key, 1);
+        }
+
+        if key < 0 {
+            -self.pow_gamma(-key - self.offset - 1)
+        } else if key > 0 {
+            self.pow_gamma(key - self.offset)
+        } else {
+            self.min_value
+        }
+    }
+
+    pub fn log_gamma(&self, value: f64) -> f64 {
         log_gamma(value, self.gamma_ln)
     }
 
-    pub fn pow_gamma(self: &Self, k: i32) -> f64 {
+    pub fn pow_gamma(&self, k: i32) -> f64 {
         ((k as f64) * self.gamma_ln).exp()
     }
+
+    // gamma raised to `k` plus `half` halves, for the bin bounds of a log-centered config
+    fn pow_gamma_half(&self, k: i32, half: i32) -> f64 {
+        ((k as f64 + half as f64 * 0.5) * self.gamma_ln).exp()
+    }
+}
+
+/// Return the number of bins a sketch with relative accuracy `alpha` needs to cover every
+/// value from `min` to `max` without collapsing, using the default minimum value. For example
+/// `required_bins(0.01, 1.0e-6, 60.0)` is the bin limit needed to keep latencies from 1µs to
+/// 60s at 1% accuracy. The range may span zero; if `min` exceeds `max` the result is 0.
+pub fn required_bins(alpha: f64, min: f64, max: f64) -> u32 {
+    if min.is_nan() || max.is_nan() || min > max {
+        return 0;
+    }
+
+    let c = Config::new(alpha, DEFAULT_MAX_BINS, DEFAULT_MIN_VALUE);
+    (c.key(max) as i64 - c.key(min) as i64 + 1).min(u32::MAX as i64) as u32
+}
+
+/// The parameter `ConfigBuilder::build` rejected
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The relative accuracy was not strictly between 0.0 and 1.0
+    Alpha,
+    /// The bin limit was zero or too large for the store
+    MaxNumBins,
+    /// The minimum value was not positive and finite
+    MinValue,
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Alpha => write!(f, "Invalid alpha, must be between 0 and 1 (exclusive)"),
+            ConfigError::MaxNumBins => {
+                write!(f, "Invalid bin limit, must be between 1 and {}", i32::MAX)
+            }
+            ConfigError::MinValue => {
+                write!(f, "Invalid minimum value, must be positive and finite")
+            }
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for ConfigError {}
+
+/// Builds a `Config` from named parameters, as returned by `Config::builder`. Any parameter
+/// that is not set keeps its built-in default.
+///
+/// ```
+/// use sketches_ddsketch::Config;
+///
+/// let c = Config::builder().alpha(0.02).max_num_bins(1024).build().unwrap();
+/// assert_eq!(c, Config::new(0.02, 1024, 1.0e-9));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ConfigBuilder {
+    alpha: f64,
+    max_num_bins: u32,
+    min_value: f64,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        ConfigBuilder {
+            alpha: DEFAULT_ALPHA,
+            max_num_bins: DEFAULT_MAX_BINS,
+            min_value: DEFAULT_MIN_VALUE,
+        }
+    }
+}
+
+impl ConfigBuilder {
+    /// Set the relative accuracy
+    pub fn alpha(mut self, alpha: f64) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Set the max number of bins the DDSketch will grow to
+    pub fn max_num_bins(mut self, max_num_bins: u32) -> Self {
+        self.max_num_bins = max_num_bins;
+        self
+    }
+
+    /// Set the magnitude below which values are mapped to the zero bin
+    pub fn min_value(mut self, min_value: f64) -> Self {
+        self.min_value = min_value;
+        self
+    }
+
+    /// Return the `Config` that `Config::new` builds from the parameters. Result is an error if
+    /// alpha is not strictly between 0.0 and 1.0, the bin limit is zero or exceeds `i32::MAX`,
+    /// or the minimum value is not positive and finite.
+    pub fn build(&self) -> Result<Config, ConfigError> {
+        if self.alpha.is_nan() || self.alpha <= 0.0 || self.alpha >= 1.0 {
+            return Err(ConfigError::Alpha);
+        }
+        if self.max_num_bins == 0 || self.max_num_bins > i32::MAX as u32 {
+            return Err(ConfigError::MaxNumBins);
+        }
+        if !self.min_value.is_finite() || self.min_value <= 0.0 {
+            return Err(ConfigError::MinValue);
+        }
+
+        Ok(Config::new(self.alpha, self.max_num_bins, self.min_value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{required_bins, Config, ConfigError, DDSketch, Provenance};
+
+    #[test]
+    fn test_snap_alpha() {
+        let allowed = [0.01, 0.005, 0.001];
+
+        let c = Config::new(0.0103, 2048, 1.0e-9);
+        assert_eq!(c.snap_alpha(&allowed), Config::new(0.01, 2048, 1.0e-9));
+
+        let c = Config::new(0.0012, 1024, 1.0e-6);
+        assert_eq!(c.snap_alpha(&allowed), Config::new(0.001, 1024, 1.0e-6));
+
+        assert_eq!(c.snap_alpha(&[]), c);
+
+        let c = c.with_max_value(1.0e6);
+        assert_eq!(c.snap_alpha(&allowed).max_value(), 1.0e6);
+    }
+
+    #[test]
+    fn test_from_gamma() {
+        let d = Config::defaults();
+        let c = Config::from_gamma(d.gamma, 2048, 1.0e-9);
+
+        assert_eq!(c.gamma, d.gamma);
+        assert_eq!(c.offset, d.offset);
+        for i in -1000..1000 {
+            let v = (i as f64) * 1.37;
+            assert_eq!(c.key(v), d.key(v));
+        }
+        for k in &[-2000, -5, 0, 5, 2000] {
+            assert!((c.value(*k) - d.value(*k)).abs() <= d.value(*k).abs() * 1.0e-12);
+        }
+    }
+
+    #[test]
+    fn test_agent_with_alpha() {
+        let agent = Config::agent_defaults();
+        let c = Config::agent_with_alpha(1.0 / 128.0);
+
+        assert_eq!(c, agent);
+        for v in &[-1.0e6, -3.5, 1.0e-9, 0.0, 0.5, 1.0, 42.0, 1.0e9] {
+            assert_eq!(c.key(*v), agent.key(*v));
+        }
+
+        let coarse = Config::agent_with_alpha(0.02);
+        assert_eq!(coarse.gamma, 1.04);
+        assert_eq!(coarse.max_num_bins, agent.max_num_bins);
+        assert_eq!(coarse.min_value, agent.min_value);
+        assert!(coarse.key(1.0e6) < agent.key(1.0e6));
+    }
+
+    #[test]
+    fn test_is_finer_than() {
+        let fine = Config::new(0.005, 2048, 1.0e-9);
+        let coarse = Config::new(0.02, 1024, 1.0e-9);
+
+        assert!(fine.is_finer_than(&coarse));
+        assert!(!coarse.is_finer_than(&fine));
+        assert!(!fine.is_finer_than(&fine));
+
+        // A larger min_value lumps more values into the zero bin
+        let wide_zero = Config::new(0.005, 2048, 1.0e-3);
+        assert!(!wide_zero.is_finer_than(&coarse));
+        assert!(fine.is_finer_than(&Config::new(0.02, 1024, 1.0e-3)));
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(Config::defaults().describe(), "library-default");
+        assert_eq!(
+            Config::new(0.01, 2048, 1.0e-9).describe(),
+            "library-default"
+        );
+        assert_eq!(Config::agent_defaults().describe(), "agent-default");
+        assert_eq!(Config::new(0.02, 2048, 1.0e-9).describe(), "custom");
+        assert_eq!(Config::new(0.01, 1024, 1.0e-9).describe(), "custom");
+    }
+
+    #[test]
+    fn test_bin_bounds() {
+        let c = Config::defaults();
+
+        for v in &[-5000.0, -1.5, -1.0e-3, 1.0e-3, 0.75, 1.0, 250.0, 1.0e9] {
+            let key = c.key(*v);
+            assert!(c.lower_bound(key) <= *v && *v <= c.upper_bound(key));
+            assert!(c.lower_bound(key) <= c.value(key) && c.value(key) <= c.upper_bound(key));
+            assert_eq!(c.upper_bound(key), c.lower_bound(key + 1));
+        }
+
+        assert_eq!(c.lower_bound(0), -1.0e-9);
+        assert_eq!(c.upper_bound(0), 1.0e-9);
+    }
+
+    #[test]
+    fn test_required_bins() {
+        for (alpha, min, max) in &[
+            (0.01, 1.0e-6, 60.0),
+            (0.02, 0.5, 0.5),
+            (0.005, 1.0, 1.0e9),
+            (0.01, -250.0, 3.0e4),
+        ] {
+            let n = required_bins(*alpha, *min, *max);
+            let mut d = DDSketch::new(Config::new(*alpha, n, 1.0e-9));
+            d.add(*min);
+            d.add(*max);
+
+            assert_eq!(d.span_bins(), n as usize);
+            assert_eq!(d.collapse_events(), 0);
+            assert!(d.length() >= n as usize);
+        }
+
+        assert_eq!(required_bins(0.01, 5.0, 1.0), 0);
+        assert!(required_bins(0.001, 1.0e-6, 60.0) > required_bins(0.01, 1.0e-6, 60.0));
+    }
+
+    #[test]
+    fn test_provenance() {
+        let defaults = Config::defaults();
+        let custom = Config::new(0.01, 2048, 1.0e-9);
+        assert_eq!(defaults, custom);
+        assert_eq!(defaults.provenance(), Provenance::Default);
+        assert_eq!(custom.provenance(), Provenance::Custom);
+
+        let agent = Config::agent_defaults();
+        let from_gamma = Config::from_gamma(1.0 + 2.0 / 128.0, 4096, 1.0e-9);
+        assert_eq!(agent, from_gamma);
+        assert_eq!(agent.provenance(), Provenance::AgentDefault);
+        assert_eq!(from_gamma.provenance(), Provenance::FromGamma);
+
+        // Provenance has no bearing on merging
+        let mut d1 = DDSketch::new(defaults);
+        let mut d2 = DDSketch::new(custom);
+        d1.add(1.0);
+        d2.add(2.0);
+        assert!(d1.merge(&d2).is_ok());
+    }
+
+    #[test]
+    fn test_accessors() {
+        let c = Config::new(0.01, 1024, 1.0e-6);
+        assert!((c.alpha() - 0.01).abs() <= 1.0e-12);
+        assert_eq!(c.gamma(), c.gamma);
+        assert_eq!(c.max_num_bins(), 1024);
+        assert_eq!(c.min_value(), 1.0e-6);
+
+        let agent = Config::agent_defaults();
+        assert!((agent.alpha() - 1.0 / 129.0).abs() <= 1.0e-12);
+        assert_eq!(agent.gamma(), 1.0 + 2.0 / 128.0);
+    }
+
+    #[test]
+    fn test_builder() {
+        let c = Config::builder()
+            .alpha(0.02)
+            .max_num_bins(1024)
+            .min_value(1.0e-6)
+            .build()
+            .unwrap();
+        assert_eq!(c, Config::new(0.02, 1024, 1.0e-6));
+        assert_eq!(Config::builder().build().unwrap(), Config::defaults());
+
+        for alpha in &[0.0, -0.1, 1.0, 1.5, f64::NAN] {
+            let err = Config::builder().alpha(*alpha).build();
+            assert_eq!(err, Err(ConfigError::Alpha));
+        }
+        for bins in &[0, u32::MAX] {
+            let err = Config::builder().max_num_bins(*bins).build();
+            assert_eq!(err, Err(ConfigError::MaxNumBins));
+        }
+        for min_value in &[0.0, -1.0e-9, f64::INFINITY, f64::NAN] {
+            let err = Config::builder().min_value(*min_value).build();
+            assert_eq!(err, Err(ConfigError::MinValue));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "max_num_bins must not exceed i32::MAX")]
+    fn test_reject_huge_max_num_bins() {
+        Config::new(0.01, u32::MAX, 1.0e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "max_num_bins must not exceed i32::MAX")]
+    fn test_reject_huge_max_num_bins_field() {
+        let mut c = Config::defaults();
+        c.max_num_bins = i32::MAX as u32 + 1;
+        DDSketch::new(c);
+    }
 }
@@ -1,19 +1,27 @@
-use std::error;
-use std::f64::INFINITY;
-use std::fmt;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::collections::HashSet;
 
-use crate::config::Config;
+use crate::config::{CollapseStrategy, Config};
 use crate::store::Store;
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use crate::Float;
 
-type Result<T> = std::result::Result<T, DDSketchError>;
+type Result<T> = core::result::Result<T, DDSketchError>;
 
-/// General error type for DDSketch, represents either an invalid quantile or an
-/// incompatible merge operation.
+/// General error type for DDSketch, represents either an invalid quantile, an
+/// incompatible merge operation, a malformed encoded sketch, extremes that contradict
+/// each other or a NaN value where a threshold was expected.
 ///
 #[derive(Debug, Clone)]
 pub enum DDSketchError {
     Quantile,
     Merge,
+    BadFormat,
+    Extremes,
+    Value,
 }
 impl fmt::Display for DDSketchError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -22,64 +30,407 @@ impl fmt::Display for DDSketchError {
                 write!(f, "Invalid quantile, must be between 0 and 1 (inclusive)")
             }
             DDSketchError::Merge => write!(f, "Can not merge sketches with different configs"),
+            DDSketchError::BadFormat => write!(f, "Can not decode a malformed sketch"),
+            DDSketchError::Extremes => write!(f, "Invalid extremes, min must not exceed max"),
+            DDSketchError::Value => write!(f, "Invalid value, must not be NaN"),
         }
     }
 }
-impl error::Error for DDSketchError {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+#[cfg(feature = "std")]
+impl std::error::Error for DDSketchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         // Generic
         None
     }
 }
 
-/// This struct represents a [DDSketch](https://arxiv.org/pdf/1908.10693.pdf)
-#[derive(Clone)]
-pub struct DDSketch {
+/// The per-bin changes and summary state needed to bring a baseline sketch up to date, as
+/// produced by `DDSketch::diff_since` and replayed with `DDSketch::apply_diff`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SketchDiff {
     config: Config,
-    store: Store,
+    bins: Vec<(i32, i64)>,
     min: f64,
     max: f64,
+    min_max_trusted: bool,
     sum: f64,
+    nan_count: i64,
+    inf_count: i64,
+    out_of_range_count: i64,
+}
+
+impl SketchDiff {
+    /// Returns the changed bins as `(key, delta)` pairs in ascending key order
+    pub fn bins(&self) -> &[(i32, i64)] {
+        &self.bins
+    }
+
+    /// Returns the change in the sum of values
+    pub fn sum_delta(&self) -> f64 {
+        self.sum
+    }
+
+    /// Returns true if applying the diff would not change any bin, the sum or the rejected
+    /// sample counters
+    pub fn is_empty(&self) -> bool {
+        self.bins.is_empty()
+            && self.sum == 0.0
+            && self.nan_count == 0
+            && self.inf_count == 0
+            && self.out_of_range_count == 0
+    }
+}
+
+/// Statistics about a batch of values ingested with `DDSketch::add_all`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IngestStats {
+    /// The number of values added to the distribution
+    pub added: u64,
+    /// The number of non-finite or out of range values that were counted but not added
+    pub skipped: u64,
+    /// The length of the store before the batch
+    pub bins_before: usize,
+    /// The length of the store after the batch
+    pub bins_after: usize,
+}
+
+/// The outcome of `DDSketch::merge_and_report`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MergeReport {
+    /// Whether bins had to be collapsed during the merge to stay within `max_num_bins`
+    pub collapsed: bool,
+    /// The length of the store after the merge
+    pub bins_after: usize,
+    /// The worst relative error of any sample's reconstructed value after the merge, which is
+    /// the config's relative accuracy unless bins have been collapsed
+    pub worst_case_rel_error: f64,
+}
+
+/// How `DDSketch::smart_merge` combined the two sketches
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MergeKind {
+    /// The bins lined up and were merged exactly, as by `merge_relaxed`
+    Exact,
+    /// The accuracies differed, so the other sketch's bins were re-binned, as by `merge_rebin`
+    Folded,
+}
+
+/// A snapshot of a sketch's accuracy and size, as returned by `DDSketch::health_report`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HealthReport {
+    /// The relative accuracy guaranteed by the config
+    pub relative_accuracy: f64,
+    /// The number of bins holding at least one sample
+    pub occupied_bins: usize,
+    /// The length of the store
+    pub allocated_bins: usize,
+    /// The number of values added to the sketch
+    pub total_count: u64,
+    /// Whether samples have been collapsed, or the populated keys already span `max_num_bins`
+    /// so that any sample beyond them would be
+    pub is_saturated: bool,
+}
+
+/// The statistics a reporting loop typically scrapes from a sketch, as returned by
+/// `DDSketch::report`. The fields match the corresponding `DDSketch` accessors, with `p50`,
+/// `p90` and `p99` being the quantiles 0.5, 0.9 and 0.99.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Report {
+    pub count: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub sum: Option<f64>,
+    pub mean: Option<f64>,
+    pub p50: Option<f64>,
+    pub p90: Option<f64>,
+    pub p99: Option<f64>,
+}
+
+/// A quantile value together with how it was derived, as returned by `DDSketch::quantile_full`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct QuantileResult {
+    /// The requested quantile
+    pub q: f64,
+    /// The quantile value, as returned by `DDSketch::quantile`
+    pub value: f64,
+    /// Whether the bin's representative value fell outside the extremes seen and was bound to
+    /// them
+    pub clamped: bool,
+    /// The number of samples in the bin the value was taken from
+    pub support: u64,
+}
+
+impl fmt::Display for QuantileResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "q{}={} (support {}", self.q, self.value, self.support)?;
+        if self.clamped {
+            write!(f, ", clamped")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Whether a quantile is backed by enough samples to be trusted, as returned by
+/// `DDSketch::quantile_sample_adequacy`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Adequacy {
+    /// The number of samples in the bin the quantile is taken from
+    pub support: u64,
+    /// Whether the support is at least `Adequacy::MIN_SUPPORT`
+    pub adequate: bool,
+}
+
+impl Adequacy {
+    /// The number of samples a bin needs for the quantile it backs to be considered adequate
+    pub const MIN_SUPPORT: u64 = 10;
+}
+
+/// Where a sample landed, as returned by `DDSketch::add_checked`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddOutcome {
+    /// The sample was added to its own bin, within the relative accuracy
+    Exact,
+    /// The sample was below the range the store could cover within `max_num_bins`, and was
+    /// added to the collapsed lowest bin
+    CollapsedLow,
+    /// The sample was above the range the store could cover within `max_num_bins`, and was
+    /// added to the collapsed highest bin under `CollapseStrategy::CollapseHighest`
+    CollapsedHigh,
+    /// The sample was NaN, infinite or out of range, and was counted but not added
+    Rejected,
+}
+
+/// This struct represents a [DDSketch](https://arxiv.org/pdf/1908.10693.pdf)
+#[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        try_from = "crate::serde_support::SketchRepr",
+        into = "crate::serde_support::SketchRepr"
+    )
+)]
+pub struct DDSketch {
+    pub(crate) config: Config,
+    pub(crate) store: Store,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    pub(crate) sum: f64,
+    pub(crate) nan_count: u64,
+    pub(crate) inf_count: u64,
+    pub(crate) out_of_range_count: u64,
+    pub(crate) max_source_bin_count: u64,
+    pub(crate) config_changed: bool,
+    pub(crate) scale_factor: f64,
+    pub(crate) min_max_trusted: bool,
 }
 
 // XXX: functions should return Option<> in the case of empty
 impl DDSketch {
-    /// Construct a `DDSketch`. Requires a `Config` specifying the parameters of the sketch
+    /// Construct a `DDSketch`. Requires a `Config` specifying the parameters of the sketch.
+    /// Panics if the config's `max_num_bins` was set past `i32::MAX`.
     pub fn new(config: Config) -> Self {
         DDSketch {
-            config: config,
-            store: Store::new(config.max_num_bins as i32),
-            min: INFINITY,
-            max: -INFINITY,
+            config,
+            store: Store::with_strategy(config.bin_limit(), config.collapse),
+            min: f64::INFINITY,
+            max: -f64::INFINITY,
             sum: 0.0,
+            nan_count: 0,
+            inf_count: 0,
+            out_of_range_count: 0,
+            max_source_bin_count: 0,
+            config_changed: false,
+            scale_factor: 1.0,
+            min_max_trusted: true,
+        }
+    }
+
+    /// Construct a `DDSketch` whose store is pre-allocated to cover values from `min` to `max`,
+    /// so that adding values in that range never reallocates. Values outside of it still grow
+    /// the store as usual. The range is limited to the config's `max_num_bins`.
+    pub fn with_expected_range(config: Config, min: f64, max: f64) -> Self {
+        let (lo, hi) = (config.key(min.min(max)), config.key(min.max(max)));
+
+        DDSketch {
+            store: Store::with_range(config.bin_limit(), config.collapse, lo, hi),
+            ..DDSketch::new(config)
         }
     }
 
-    /// Add the sample to the sketch
+    /// Add the sample to the sketch. NaN and infinite samples are not added to the
+    /// distribution, but are counted and reported by `nan_count` and `inf_count`. Likewise,
+    /// samples beyond the config's `max_value` are counted by `out_of_range_count`.
     pub fn add(&mut self, v: f64) {
+        self.add_n(v, 1);
+    }
+
+    /// Add `n` copies of the sample to the sketch, as `n` calls to `add` would. The sum grows by
+    /// `v * n`, so the mean stays the weighted average of the samples.
+    pub fn add_n(&mut self, v: f64, n: u64) {
+        self.insert(v, n);
+    }
+
+    /// Add an `f32` sample to the sketch, as `add` would with the sample widened to `f64`.
+    /// Widening is exact, so the sample is bucketed by the value the `f32` actually holds rather
+    /// than the decimal it was written as. Near `min_value` the two can fall on opposite sides:
+    /// `1.0e-9_f32` holds slightly less than `1.0e-9`, so with the default minimum value it lands
+    /// in the zero bin.
+    pub fn add_f32(&mut self, v: f32) {
+        self.add(f64::from(v));
+    }
+
+    /// Add `n` copies of an `f32` sample to the sketch, as `add_n` would with the sample widened
+    /// to `f64`. See `add_f32`.
+    pub fn add_n_f32(&mut self, v: f32, n: u64) {
+        self.add_n(f64::from(v), n);
+    }
+
+    /// Add the sample to the sketch as `add` would, and report whether it kept its own bin or
+    /// had to be collapsed into the bin at the end of the key range because the store reached
+    /// `max_num_bins`. A collapsed sample is only bounded by its neighbours rather than the
+    /// relative accuracy, so counting these is a way to detect that the config is too narrow
+    /// for the data.
+    pub fn add_checked(&mut self, v: f64) -> AddOutcome {
+        self.insert(v, 1)
+    }
+
+    fn insert(&mut self, v: f64, n: u64) -> AddOutcome {
+        if n == 0 {
+            return AddOutcome::Exact;
+        }
+        if !v.is_finite() {
+            if v.is_nan() {
+                self.nan_count += n;
+            } else {
+                self.inf_count += n;
+            }
+            return AddOutcome::Rejected;
+        }
+        if !self.config.in_range(v) {
+            self.out_of_range_count += n;
+            return AddOutcome::Rejected;
+        }
+
         let key = self.config.key(v);
 
-        self.store.add(key);
+        let collapsed = self.store.add_n(key, n);
+
+        if v < self.min {
+            self.min = v;
+        }
+        if self.max < v {
+            self.max = v;
+        }
+        self.sum += v * n as f64;
+
+        match (collapsed, self.config.collapse) {
+            (false, _) => AddOutcome::Exact,
+            (true, CollapseStrategy::CollapseLowest) => AddOutcome::CollapsedLow,
+            (true, CollapseStrategy::CollapseHighest) => AddOutcome::CollapsedHigh,
+        }
+    }
+
+    /// Add `n` samples directly to the bin `key`, such as when importing pre-binned data from
+    /// another sketch with the same config. The exact values are unknown, so the extremes and
+    /// sum are updated with the bin's representative value from `Config::value`.
+    pub fn add_key_n(&mut self, key: i32, n: u64) {
+        if n == 0 {
+            return;
+        }
+
+        self.store.add_n(key, n);
+        self.min_max_trusted = false;
 
+        let v = self.config.value(key);
         if v < self.min {
             self.min = v;
         }
         if self.max < v {
             self.max = v;
         }
-        self.sum += v;
+        self.sum += v * n as f64;
+    }
+
+    /// Remove a sample previously added with `add`, such as when it expires from a sliding
+    /// window. See `remove_n`.
+    pub fn remove(&mut self, v: f64) {
+        self.remove_n(v, 1);
+    }
+
+    /// Remove `n` copies of a sample previously added, taking them from the bin `v` maps to
+    /// and subtracting them from the sum. At most the bin's count is removed, and values that
+    /// `add` would have rejected are ignored.
+    ///
+    /// The min and max can not be restored once their samples are removed, so they are left as
+    /// they were and may become a loose lower and upper bound on the remaining samples. They
+    /// are reset once the sketch is empty.
+    pub fn remove_n(&mut self, v: f64, n: u64) {
+        if !v.is_finite() || !self.config.in_range(v) {
+            return;
+        }
+
+        let removed = self.store.remove_n(self.config.key(v), n);
+        if removed > 0 {
+            self.min_max_trusted = false;
+        }
+        self.sum -= v * removed as f64;
+        self.reset_if_empty();
+    }
+
+    /// Add every sample from `iter`, as `add` would, and report how many were added or skipped
+    /// and how the store grew.
+    pub fn add_all<I: IntoIterator<Item = f64>>(&mut self, iter: I) -> IngestStats {
+        let bins_before = self.length();
+        let count_before = self.store.count();
+        let mut total = 0;
+
+        for v in iter {
+            total += 1;
+            self.add(v);
+        }
+
+        let added = self.store.count() - count_before;
+        IngestStats {
+            added,
+            skipped: total - added,
+            bins_before,
+            bins_after: self.length(),
+        }
+    }
+
+    /// Remove every sample and reset the extremes, sum, counters and `config_changed`, so the
+    /// sketch can be reused as if newly constructed with its current config. The store keeps its allocation, so a
+    /// sketch reused across reporting intervals does not reallocate once it has grown to fit.
+    pub fn clear(&mut self) {
+        self.store.clear();
+        self.min = f64::INFINITY;
+        self.max = -f64::INFINITY;
+        self.sum = 0.0;
+        self.nan_count = 0;
+        self.inf_count = 0;
+        self.out_of_range_count = 0;
+        self.max_source_bin_count = 0;
+        self.scale_factor = 1.0;
+        self.min_max_trusted = true;
+        self.config_changed = false;
     }
 
     /// Return the quantile value for quantiles between 0.0 and 1.0. Result is an error, represented
     /// as DDSketchError::Quantile if the requested quantile is outside of that range.
     ///
     /// If the sketch is empty the result is None, else Some(v) for the quantile value.
+    ///
+    /// The quantiles 0.0 and 1.0 are the exact extremes seen, as returned by `min_observed` and
+    /// `max_observed`, while every quantile in between is reconstructed from its bin and so is
+    /// only accurate to within the relative accuracy. The result can therefore jump by up to
+    /// that error between, say, 0.9999 and 1.0.
     pub fn quantile(&self, q: f64) -> Result<Option<f64>> {
-        if q < 0.0 || q > 1.0 {
+        if !(0.0..=1.0).contains(&q) {
             return Err(DDSketchError::Quantile);
         }
 
-        if self.empty() {
+        if self.is_empty() {
             return Ok(None);
         }
 
@@ -90,146 +441,2019 @@ impl DDSketch {
         }
 
         let rank = (q * ((self.count() - 1) as f64) + 1.0) as u64;
-        let mut key = self.store.key_at_rank(rank);
-
-        let quantile;
-        if key < 0 {
-            key += self.config.offset;
-            quantile = -2.0 * self.config.pow_gamma(-key) / (1.0 + self.config.gamma);
-        } else if key > 0 {
-            key -= self.config.offset;
-            quantile = 2.0 * self.config.pow_gamma(key) / (1.0 + self.config.gamma);
-        } else {
-            quantile = 0.0;
+
+        Ok(Some(self.value_at_rank(rank)))
+    }
+
+    /// Return a pessimistic estimate of the quantile for `q`: the upper bound of the bin holding
+    /// it rather than the bin's representative value, bound by the max. The true quantile is at
+    /// most this value, which suits statements like "p99 latency is at most X" for SLO
+    /// reporting. As with `quantile`, 0.0 and 1.0 give the exact extremes.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to
+    /// 1.0. If the sketch is empty the result is None.
+    pub fn quantile_conservative(&self, q: f64) -> Result<Option<f64>> {
+        self.bin_bound_quantile(q, Config::upper_bound)
+    }
+
+    /// Return an optimistic estimate of the quantile for `q`: the lower bound of the bin holding
+    /// it, bound by the min. The true quantile is at least this value, so together with
+    /// `quantile_conservative` this brackets it using the bin geometry alone. As with
+    /// `quantile`, 0.0 and 1.0 give the exact extremes.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to
+    /// 1.0. If the sketch is empty the result is None.
+    pub fn quantile_optimistic(&self, q: f64) -> Result<Option<f64>> {
+        self.bin_bound_quantile(q, Config::lower_bound)
+    }
+
+    /// Return the quantile value for each of `qs`, in the same order, with the same semantics
+    /// as `quantile`. The quantiles are sorted so that a single pass over the store finds them
+    /// all, rather than one pass per quantile.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if any of `qs` is outside of
+    /// 0.0 to 1.0. If the sketch is empty every value is None.
+    pub fn quantiles(&self, qs: &[f64]) -> Result<Vec<Option<f64>>> {
+        if qs.iter().any(|q| !(0.0..=1.0).contains(q)) {
+            return Err(DDSketchError::Quantile);
         }
 
-        // Bound by the extremes
-        let ret;
-        if quantile < self.min {
-            ret = self.min;
-        } else if quantile > self.max {
-            ret = self.max;
-        } else {
-            ret = quantile;
+        if self.is_empty() {
+            return Ok(vec![None; qs.len()]);
+        }
+
+        let mut order: Vec<usize> = (0..qs.len()).collect();
+        order.sort_by(|&a, &b| qs[a].partial_cmp(&qs[b]).unwrap());
+        let ranks: Vec<u64> = order
+            .iter()
+            .map(|&i| (qs[i] * ((self.count() - 1) as f64) + 1.0) as u64)
+            .collect();
+        let reconstructed = self.values_at_ranks(&ranks);
+
+        let mut values = vec![None; qs.len()];
+        for (&i, value) in order.iter().zip(reconstructed) {
+            values[i] = Some(if qs[i] == 0.0 {
+                self.min
+            } else if qs[i] == 1.0 {
+                self.max
+            } else {
+                value
+            });
         }
 
-        Ok(Some(ret))
+        Ok(values)
     }
 
-    /// Returns the minimum value seen, or None if sketch is empty
-    pub fn min(&self) -> Option<f64> {
-        if self.empty() {
-            None
-        } else {
-            Some(self.min)
+    /// Write the quantile value for each of `qs` into the matching slot of `out`, with the same
+    /// semantics as `quantile`. The cumulative counts of the bins are built in `scratch`, which
+    /// is cleared first, so reusing the same vector across calls avoids allocating once it has
+    /// grown to fit the largest sketch queried. `out` must be at least as long as `qs`.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if any of `qs` is outside of
+    /// 0.0 to 1.0, in which case `out` is left untouched. If the sketch is empty every slot is
+    /// set to None.
+    pub fn quantiles_with_scratch(
+        &self,
+        qs: &[f64],
+        scratch: &mut Vec<(u64, i32)>,
+        out: &mut [Option<f64>],
+    ) -> Result<()> {
+        assert!(out.len() >= qs.len(), "out is shorter than qs");
+        if qs.iter().any(|q| !(0.0..=1.0).contains(q)) {
+            return Err(DDSketchError::Quantile);
+        }
+
+        scratch.clear();
+        let mut n = 0;
+        scratch.extend(self.store.bins().map(|(key, count)| {
+            n += count;
+            (n, key)
+        }));
+
+        for (q, slot) in qs.iter().zip(out.iter_mut()) {
+            *slot = if self.is_empty() {
+                None
+            } else if *q == 0.0 {
+                Some(self.min)
+            } else if *q == 1.0 {
+                Some(self.max)
+            } else {
+                let rank = (q * ((self.count() - 1) as f64) + 1.0) as u64;
+                let i = scratch.partition_point(|(n, _)| *n < rank);
+                let key = scratch[i.min(scratch.len() - 1)].1;
+                Some(self.config.value(key).max(self.min).min(self.max))
+            };
         }
+
+        Ok(())
     }
 
-    /// Returns the maximum value seen, or None if sketch is empty
-    pub fn max(&self) -> Option<f64> {
-        if self.empty() {
-            None
+    /// Return the quantile value for `q` as `quantile` would, along with whether it was bound to
+    /// the extremes and how many samples share its bin. The quantiles 0.0 and 1.0 are the exact
+    /// extremes, so they are never reported as clamped.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to
+    /// 1.0. If the sketch is empty the result is None.
+    pub fn quantile_full(&self, q: f64) -> Result<Option<QuantileResult>> {
+        let value = match self.quantile(q)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let (key, clamped) = if q == 0.0 {
+            (self.config.key(self.min), false)
+        } else if q == 1.0 {
+            (self.config.key(self.max), false)
         } else {
-            Some(self.max)
-        }
+            let rank = (q * ((self.count() - 1) as f64) + 1.0) as u64;
+            let key = self.store.key_at_rank(rank);
+            let v = self.config.value(key);
+            (key, v < self.min || v > self.max)
+        };
+
+        Ok(Some(QuantileResult {
+            q,
+            value,
+            clamped,
+            support: self.store.count_at(key),
+        }))
     }
 
-    /// Returns the sum of values seen, or None if sketch is empty
-    pub fn sum(&self) -> Option<f64> {
-        if self.empty() {
-            None
+    /// Return whether the quantile for `q` is backed by enough samples to be trusted, judged by
+    /// the count of the bin it is taken from against `Adequacy::MIN_SUPPORT`. This guards
+    /// against reporting tail quantiles that rest on a single sample.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to
+    /// 1.0. If the sketch is empty the result is None.
+    pub fn quantile_sample_adequacy(&self, q: f64) -> Result<Option<Adequacy>> {
+        Ok(self.quantile_full(q)?.map(|r| Adequacy {
+            support: r.support,
+            adequate: r.support >= Adequacy::MIN_SUPPORT,
+        }))
+    }
+
+    /// Return the quantile value for `q` in the units samples had before any calls to `scale`,
+    /// by dividing the quantile of the scaled sketch by the accumulated scale factor. If the
+    /// factor is negative the sketch was mirrored, so the opposite quantile is used. This is
+    /// `quantile` for a sketch that was never scaled, and merging adopts this sketch's factor
+    /// whatever the other sketch's was.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to
+    /// 1.0. If the sketch is empty, or was scaled by zero so the original values are lost, the
+    /// result is None.
+    pub fn quantile_unscaled(&self, q: f64) -> Result<Option<f64>> {
+        let factor = self.scale_factor;
+        let q = if factor < 0.0 && (0.0..=1.0).contains(&q) {
+            1.0 - q
         } else {
-            Some(self.sum)
+            q
+        };
+
+        match self.quantile(q)? {
+            Some(v) if factor != 0.0 => Ok(Some(v / factor)),
+            _ => Ok(None),
         }
     }
 
-    /// Returns the number of values added to the sketch
-    pub fn count(&self) -> usize {
-        self.store.count() as usize
+    /// Returns the product of every factor passed to `scale`, or 1.0 if it was never called
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
     }
 
-    /// Returns the length of the underlying `Store`. This is mainly only useful for understanding
-    /// how much the sketch has grown given the inserted values.
-    pub fn length(&self) -> usize {
-        self.store.length() as usize
+    /// Return `(q, value)` pairs for the tail quantiles `q = 1 - 10^-k` for `k` in `1..=nines`,
+    /// i.e. p90, p99, p99.9 and so on, computed in a single pass over the store. The result is
+    /// empty if the sketch is empty.
+    pub fn tail_quantiles(&self, nines: usize) -> Vec<(f64, f64)> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        let qs: Vec<f64> = (1..=nines).map(|k| 1.0 - 10f64.powi(-(k as i32))).collect();
+        let ranks: Vec<u64> = qs
+            .iter()
+            .map(|q| (q * ((self.count() - 1) as f64) + 1.0) as u64)
+            .collect();
+
+        qs.iter()
+            .zip(self.values_at_ranks(&ranks))
+            .map(|(&q, value)| (q, if q == 1.0 { self.max } else { value }))
+            .collect()
     }
 
-    /// Merge the contents of another sketch into this one. The sketch that is merged into this one
-    /// is unchanged after the merge.
-    pub fn merge(&mut self, o: &DDSketch) -> Result<()> {
-        if self.config != o.config {
-            return Err(DDSketchError::Merge);
+    /// Return the quantile for `q` as `(estimate, lower, upper)`, where the bounds are the
+    /// interval the relative accuracy guarantees the true quantile lies in: `estimate * (1 -
+    /// alpha)` to `estimate * (1 + alpha)` for a positive estimate, mirrored for a negative one,
+    /// and bound by the extremes. Unlike `quantile_with_ci` this does not account for sampling
+    /// error, nor for bins that have been collapsed.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to
+    /// 1.0. If the sketch is empty the result is None.
+    pub fn quantile_with_bounds(&self, q: f64) -> Result<Option<(f64, f64, f64)>> {
+        let estimate = match self.quantile(q)? {
+            Some(estimate) => estimate,
+            None => return Ok(None),
+        };
+
+        let margin = estimate.abs() * self.config.alpha();
+        let lower = (estimate - margin).max(self.min);
+        let upper = (estimate + margin).min(self.max);
+
+        Ok(Some((estimate, lower, upper)))
+    }
+
+    /// Return the quantile for `q` together with an interval that accounts for both the bin's
+    /// relative accuracy and the sampling error of estimating a population quantile from
+    /// `count()` samples, as `(lower, point, upper)`.
+    ///
+    /// The sampling interval assumes the samples are independent draws from the population, and
+    /// uses the normal approximation to the binomial distribution of the number of samples below
+    /// the true quantile: the bounding ranks are `nq ± z * sqrt(nq(1 - q))`, where `z` is the
+    /// two-sided standard normal critical value for `confidence`. The values at those ranks are
+    /// then widened by the relative accuracy. The approximation is rough for very small counts or
+    /// extreme quantiles, where the ranks are clamped to the observed range.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to
+    /// 1.0 or `confidence` is not strictly between 0.0 and 1.0. If the sketch is empty the result
+    /// is None.
+    pub fn quantile_with_ci(&self, q: f64, confidence: f64) -> Result<Option<(f64, f64, f64)>> {
+        if !(confidence > 0.0 && confidence < 1.0) {
+            return Err(DDSketchError::Quantile);
         }
 
-        let was_empty = self.store.count() == 0;
+        let point = match self.quantile(q)? {
+            Some(point) => point,
+            None => return Ok(None),
+        };
 
-        // Merge the stores
-        self.store.merge(&o.store);
+        let n = self.count() as f64;
+        let z = normal_critical_value((1.0 - confidence) / 2.0);
+        let spread = z * (n * q * (1.0 - q)).sqrt();
+        let rank = |r: f64| r.round().max(1.0).min(n) as u64;
 
-        // Need to ensure we don't override min/max with initializers
-        // if either store were empty
-        if was_empty {
-            self.min = o.min;
-            self.max = o.max;
-        } else if o.store.count() > 0 {
-            if o.min < self.min {
-                self.min = o.min
+        let alpha = self.config.alpha();
+        let lower = self.value_at_rank(rank(n * q - spread));
+        let upper = self.value_at_rank(rank(n * q + spread));
+        let lower = (lower - alpha * lower.abs()).min(point);
+        let upper = (upper + alpha * upper.abs()).max(point);
+
+        Ok(Some((lower, point, upper)))
+    }
+
+    /// Return the approximate fraction of values that are less than or equal to `v`, or None if
+    /// the sketch is empty. Values below the minimum seen give 0.0 and values at or above the
+    /// maximum seen give 1.0.
+    pub fn cdf(&self, v: f64) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+
+        if v < self.min {
+            return Some(0.0);
+        } else if v >= self.max {
+            return Some(1.0);
+        }
+
+        let key = self.config.key(v);
+        let n: u64 = self
+            .store
+            .bins()
+            .take_while(|(k, _)| *k <= key)
+            .map(|(_, count)| count)
+            .sum();
+
+        Some((n as f64 / self.count() as f64).min(1.0))
+    }
+
+    /// Return the `cdf` of each of `thresholds`, in the same order, computed in a single walk
+    /// of the store rather than one per threshold. If the sketch is empty every entry is None.
+    pub fn cdf_many(&self, thresholds: &[f64]) -> Vec<Option<f64>> {
+        if self.is_empty() {
+            return vec![None; thresholds.len()];
+        }
+
+        let mut out = vec![None; thresholds.len()];
+        let mut pending = Vec::with_capacity(thresholds.len());
+        for (i, v) in thresholds.iter().enumerate() {
+            if *v < self.min {
+                out[i] = Some(0.0);
+            } else if *v >= self.max {
+                out[i] = Some(1.0);
+            } else {
+                pending.push((self.config.key(*v), i));
             }
-            if o.max > self.max {
-                self.max = o.max;
+        }
+        pending.sort_unstable();
+
+        let count = self.count() as f64;
+        let mut bins = self.store.bins().peekable();
+        let mut n = 0;
+        for (key, i) in pending {
+            while let Some(&(k, c)) = bins.peek() {
+                if k > key {
+                    break;
+                }
+                n += c;
+                bins.next();
             }
+            out[i] = Some((n as f64 / count).min(1.0));
         }
-        self.sum += o.sum;
 
-        Ok(())
+        out
     }
 
-    fn empty(&self) -> bool {
-        self.count() == 0
+    /// Return the approximate number of values between `low` and `high`, inclusive, counted as
+    /// every sample in a bin whose key lies between the keys of the two bounds. Ranges entirely
+    /// below the minimum or above the maximum seen give 0. Result is None if the sketch is empty
+    /// or `low` exceeds `high`.
+    pub fn count_between(&self, low: f64, high: f64) -> Option<u64> {
+        if self.is_empty() || low.is_nan() || high.is_nan() || low > high {
+            return None;
+        }
+
+        if high < self.min || low > self.max {
+            return Some(0);
+        }
+
+        let (lo, hi) = (self.config.key(low), self.config.key(high));
+        let n = self
+            .store
+            .bins()
+            .skip_while(|(k, _)| *k < lo)
+            .take_while(|(k, _)| *k <= hi)
+            .map(|(_, count)| count)
+            .sum();
+
+        Some(n)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::Config;
-    use crate::DDSketch;
+    /// Return the mean absolute difference between the quantiles of this sketch and `other` at
+    /// `n` evenly spaced quantiles, taken at the midpoints `(i + 0.5) / n`. This approximates the
+    /// 1-Wasserstein distance between the two distributions, for use as a drift metric.
+    ///
+    /// Result is an error, represented as DDSketchError::Merge, if the sketches do not share the
+    /// same config, or DDSketchError::Quantile if `n` is zero. If either sketch is empty the
+    /// result is None.
+    pub fn quantile_distance(&self, other: &DDSketch, n: usize) -> Result<Option<f64>> {
+        if self.config != other.config {
+            return Err(DDSketchError::Merge);
+        }
+        if n == 0 {
+            return Err(DDSketchError::Quantile);
+        }
+        if self.is_empty() || other.is_empty() {
+            return Ok(None);
+        }
 
-    #[test]
-    fn test_simple_quantile() {
-        let c = Config::defaults();
-        let mut dd = DDSketch::new(c);
+        let mut total = 0.0;
+        for i in 0..n {
+            let q = (i as f64 + 0.5) / n as f64;
+            // Both sketches are non-empty and q is in range, so neither quantile can be missing
+            let a = self.quantile(q)?.unwrap();
+            let b = other.quantile(q)?.unwrap();
+            total += (a - b).abs();
+        }
 
-        for i in 1..101 {
-            dd.add(i as f64);
+        Ok(Some(total / n as f64))
+    }
+
+    /// Returns the minimum value seen, or None if sketch is empty
+    pub fn min(&self) -> Option<f64> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.min)
         }
+    }
 
-        assert_eq!(dd.quantile(0.95).unwrap().unwrap().ceil(), 95.0);
+    /// Returns the maximum value seen, or None if sketch is empty
+    pub fn max(&self) -> Option<f64> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.max)
+        }
+    }
 
-        assert!(dd.quantile(-1.01).is_err());
-        assert!(dd.quantile(1.01).is_err());
+    /// Return the percentile rank of `value`, from 0.0 to 100.0, as `cdf` expressed as a
+    /// percentage, or None if the sketch is empty. Feeding a quantile of another sketch into
+    /// this places it within this sketch's distribution, for example to compare a candidate's
+    /// median against a baseline.
+    pub fn percentile_rank_of(&self, value: f64) -> Option<f64> {
+        self.cdf(value).map(|p| p * 100.0)
     }
 
-    #[test]
-    fn test_empty_sketch() {
-        let c = Config::defaults();
-        let dd = DDSketch::new(c);
+    /// Return whether `value` is above the quantile for `q`, without reconstructing the quantile
+    /// value. This compares the number of samples in bins below `value`'s bin against the rank
+    /// `quantile` would look up, like checking `cdf(value) > q`, so it agrees with
+    /// `value > quantile(q)` except when `value` falls in the same bin as the quantile, where it
+    /// returns false.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to
+    /// 1.0. If the sketch is empty the result is None.
+    pub fn exceeds_quantile(&self, value: f64, q: f64) -> Result<Option<bool>> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(DDSketchError::Quantile);
+        }
 
-        assert_eq!(dd.quantile(0.98).unwrap(), None);
-        assert_eq!(dd.max(), None);
-        assert_eq!(dd.min(), None);
-        assert_eq!(dd.sum(), None);
-        assert_eq!(dd.count(), 0);
+        if self.is_empty() {
+            return Ok(None);
+        }
 
-        assert!(dd.quantile(1.01).is_err());
+        if q == 0.0 || value <= self.min {
+            return Ok(Some(value > self.min));
+        } else if q == 1.0 || value > self.max {
+            return Ok(Some(value > self.max));
+        }
+
+        let rank = (q * ((self.count() - 1) as f64) + 1.0) as u64;
+        let key = self.config.key(value);
+        let below: u64 = self
+            .store
+            .bins()
+            .take_while(|(k, _)| *k < key)
+            .map(|(_, count)| count)
+            .sum();
+
+        Ok(Some(below >= rank))
     }
 
-    #[test]
-    fn test_basic_histogram_data() {
-        let values = &[
-            0.754225035,
-            0.752900282,
-            0.752812246,
-            0.752602367,
+    /// Returns the exact minimum value seen, which is also `quantile(0.0)`, or None if sketch is
+    /// empty. This is an alias for `min`.
+    pub fn min_observed(&self) -> Option<f64> {
+        self.min()
+    }
+
+    /// Returns the exact maximum value seen, which is also `quantile(1.0)`, or None if sketch is
+    /// empty. This is an alias for `max`.
+    pub fn max_observed(&self) -> Option<f64> {
+        self.max()
+    }
+
+    /// Returns true if `min` and `max` are known to be the extremes of the samples currently in
+    /// the sketch. Lossy operations such as `remove`, `retain_bins`, `decay` or `add_key_n` clear this,
+    /// as do merges with a sketch whose extremes are not trusted, since the tracked extremes
+    /// may then be loose bounds that no remaining sample attains. Callers can then fall back
+    /// to the bounds of the outermost bins instead. Emptying the sketch makes them trusted again.
+    pub fn extremes_trusted(&self) -> bool {
+        self.min_max_trusted
+    }
+
+    /// Returns the sum of values seen, or None if sketch is empty
+    pub fn sum(&self) -> Option<f64> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.sum)
+        }
+    }
+
+    /// Returns the mean of values seen, or None if sketch is empty
+    pub fn mean(&self) -> Option<f64> {
+        if self.is_empty() {
+            None
+        } else {
+            // Rounding in the sum can push the mean just past the extremes, most visibly when
+            // every value is the same
+            Some((self.sum / self.count() as f64).max(self.min).min(self.max))
+        }
+    }
+
+    /// Returns the winsorized mean, where values below the `1 - p` quantile and above the `p`
+    /// quantile are capped at those quantiles before averaging, so a few extreme outliers cannot
+    /// dominate the result. The mean is computed from the bins, so it carries the same relative
+    /// error as the quantiles.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if `p` is outside of 0.0 to
+    /// 1.0. If the sketch is empty the result is None.
+    pub fn winsorized_mean(&self, p: f64) -> Result<Option<f64>> {
+        let (lo, hi) = match (
+            self.quantile(p.min(1.0 - p))?,
+            self.quantile(p.max(1.0 - p))?,
+        ) {
+            (Some(lo), Some(hi)) => (lo, hi),
+            _ => return Ok(None),
+        };
+
+        let total: f64 = self
+            .store
+            .bins()
+            .map(|(key, count)| self.config.value(key).max(lo).min(hi) * count as f64)
+            .sum();
+
+        Ok(Some(total / self.count() as f64))
+    }
+
+    /// Returns the number of samples whose rank falls between the ranks of the `lower_q` and
+    /// `upper_q` quantiles, i.e. the count excluding the bottom `lower_q` and top `1 - upper_q`
+    /// of samples, as used by a trimmed mean. The ranks are those `quantile` looks up, `1 + q(n -
+    /// 1)`, and a bin straddling either of them only contributes its samples ranked within,
+    /// so a fractional rank at either end excludes the sample just outside it.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if either quantile is
+    /// outside of 0.0 to 1.0 or `lower_q` is greater than `upper_q`. If the sketch is empty the
+    /// result is None.
+    pub fn trimmed_count(&self, lower_q: f64, upper_q: f64) -> Result<Option<u64>> {
+        if !(0.0..=1.0).contains(&lower_q) || !(0.0..=1.0).contains(&upper_q) || lower_q > upper_q {
+            return Err(DDSketchError::Quantile);
+        }
+
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            self.trimmed_bins(lower_q, upper_q)
+                .map(|(_, kept)| kept)
+                .sum(),
+        ))
+    }
+
+    /// Returns the mean of the samples whose rank falls between the ranks of the `lower_q` and
+    /// `upper_q` quantiles, ignoring the tails on either side, such as `trimmed_mean(0.05,
+    /// 0.95)` for a latency average that a few outliers can not skew. The samples are those
+    /// counted by `trimmed_count`, and each bin contributes its lower bound, bound by the
+    /// extremes, weighted by how many of its samples are ranked within. Since every value in a
+    /// bin is at most `2 * alpha` above its lower bound, the mean may be that much too low.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, unless `0.0 <= lower_q <
+    /// upper_q <= 1.0`. If the sketch is empty or no sample is ranked within, the result is
+    /// None.
+    pub fn trimmed_mean(&self, lower_q: f64, upper_q: f64) -> Result<Option<f64>> {
+        if !(0.0..=1.0).contains(&lower_q) || !(0.0..=1.0).contains(&upper_q) || lower_q >= upper_q
+        {
+            return Err(DDSketchError::Quantile);
+        }
+
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let mut total = 0.0;
+        let mut weight = 0;
+        for (key, kept) in self.trimmed_bins(lower_q, upper_q) {
+            total += self.config.lower_bound(key).max(self.min).min(self.max) * kept as f64;
+            weight += kept;
+        }
+        if weight == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(total / weight as f64))
+    }
+
+    // The bins holding samples ranked between the ranks of the `lower_q` and `upper_q`
+    // quantiles, `1 + q(n - 1)` as `quantile` looks them up, each paired with how many of its
+    // samples are ranked within. A fractional rank at either end excludes the sample just
+    // outside it.
+    fn trimmed_bins(&self, lower_q: f64, upper_q: f64) -> impl Iterator<Item = (i32, u64)> + '_ {
+        let n = self.count() as f64;
+        let first = (lower_q * (n - 1.0) + 1.0).ceil() as u64;
+        let last = (upper_q * (n - 1.0) + 1.0).floor() as u64;
+
+        // Each bin holds the ranks just above those of the bins before it
+        let mut seen = 0;
+        self.store
+            .bins()
+            .map_while(move |(key, count)| {
+                let (start, end) = (seen + 1, seen + count);
+                seen = end;
+                if start > last {
+                    return None;
+                }
+                Some((key, (end.min(last) + 1).saturating_sub(start.max(first))))
+            })
+            .filter(|(_, kept)| *kept > 0)
+    }
+
+    /// Returns the mean of the samples in bins above the bin of `threshold`, using each bin's
+    /// representative value, or None if no samples lie above it. With the threshold set to a
+    /// high quantile this is the expected shortfall, e.g. the average latency of requests slower
+    /// than the p95. Samples sharing the threshold's bin are excluded, as they can not be told
+    /// apart from it.
+    pub fn conditional_mean_above(&self, threshold: f64) -> Option<f64> {
+        let key = self.config.key(threshold);
+        let (total, n) =
+            self.store
+                .bins()
+                .filter(|(k, _)| *k > key)
+                .fold((0.0, 0), |(total, n), (k, count)| {
+                    let v = self.config.value(k).max(self.min).min(self.max);
+                    (total + v * count as f64, n + count)
+                });
+
+        if n == 0 {
+            None
+        } else {
+            Some(total / n as f64)
+        }
+    }
+
+    /// Returns the fraction of the total sum contributed by the samples in bins above the bin
+    /// of the quantile for `q`, using each bin's representative value, such as how much of the
+    /// total latency comes from the slowest 20% of requests with `q = 0.8`. As with
+    /// `conditional_mean_above`, samples sharing the quantile's bin are excluded.
+    ///
+    /// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to
+    /// 1.0. If the sketch is empty, or its sum is not positive so there is no share to take, the
+    /// result is None.
+    pub fn sum_fraction_above_quantile(&self, q: f64) -> Result<Option<f64>> {
+        let threshold = match self.quantile(q)? {
+            Some(threshold) if self.sum > 0.0 => threshold,
+            _ => return Ok(None),
+        };
+
+        let key = self.config.key(threshold);
+        let above: f64 = self
+            .store
+            .bins()
+            .filter(|(k, _)| *k > key)
+            .map(|(k, count)| self.config.value(k).max(self.min).min(self.max) * count as f64)
+            .sum();
+
+        Ok(Some((above / self.sum).min(1.0)))
+    }
+
+    /// Returns true if no values have been added to the sketch
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// Returns the Gini coefficient of the samples, from 0.0 when every sample is equal towards
+    /// 1.0 as the total is concentrated in ever fewer samples. It is computed by integrating the
+    /// Lorenz curve over the bins in ascending order, treating every sample in a bin as its
+    /// representative value, bound by the extremes. Spread within a bin is thus ignored, which
+    /// can understate the coefficient by roughly the relative accuracy.
+    ///
+    /// The result is None if the sketch is empty, holds any negative samples or sums to zero, as
+    /// the coefficient is only defined for non-negative quantities.
+    pub fn gini(&self) -> Option<f64> {
+        if self.is_empty() || self.min < 0.0 {
+            return None;
+        }
+
+        let values: Vec<(f64, f64)> = self
+            .store
+            .bins()
+            .map(|(key, count)| {
+                let v = self.config.value(key).max(self.min).min(self.max);
+                (v, count as f64)
+            })
+            .collect();
+        let total: f64 = values.iter().map(|(v, n)| v * n).sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let n = self.count() as f64;
+        let (mut share, mut area) = (0.0, 0.0);
+        for (v, count) in values {
+            let next = share + v * count / total;
+            area += count / n * (share + next);
+            share = next;
+        }
+
+        Some((1.0 - area).max(0.0))
+    }
+
+    /// Returns the number of values added to the sketch
+    pub fn count(&self) -> usize {
+        self.store.count() as usize
+    }
+
+    /// Returns the number of values that fell in the zero bin, i.e. within `min_value` of zero.
+    /// This is always 0 for a log-centered sketch, which has no zero bin.
+    pub fn zero_count(&self) -> u64 {
+        if self.config.log_centered {
+            return 0;
+        }
+        self.store.count_at(0)
+    }
+
+    /// Returns the fraction of values that fell in the zero bin, or None if the sketch is empty.
+    /// A high fraction for data that should rarely be zero suggests `min_value` is too large
+    /// for it, as values the config can not tell from zero lose all resolution.
+    pub fn zero_bin_fraction(&self) -> Option<f64> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.zero_count() as f64 / self.count() as f64)
+        }
+    }
+
+    /// Returns the number of values outside of the zero bin, which is useful when zero means
+    /// no activity rather than a measurement
+    pub fn nonzero_count(&self) -> u64 {
+        self.store.count() - self.zero_count()
+    }
+
+    /// Returns the number of NaN samples that were rejected by `add`
+    pub fn nan_count(&self) -> u64 {
+        self.nan_count
+    }
+
+    /// Returns the number of infinite samples that were rejected by `add`
+    pub fn inf_count(&self) -> u64 {
+        self.inf_count
+    }
+
+    /// Returns the number of samples rejected by `add` for exceeding the config's `max_value`,
+    /// or for not being strictly positive under a log-centered config
+    pub fn out_of_range_count(&self) -> u64 {
+        self.out_of_range_count
+    }
+
+    /// Returns the number of times bins were collapsed to keep the store within `max_num_bins`,
+    /// over the lifetime of this sketch and every sketch merged into it. Each collapse loses
+    /// accuracy for the lowest values, or the highest ones under
+    /// `CollapseStrategy::CollapseHighest`, so a high count suggests the sketch should be
+    /// retired or configured with more bins.
+    pub fn collapse_events(&self) -> u64 {
+        self.store.collapse_count
+    }
+
+    /// Returns the length of the underlying `Store`. This is mainly only useful for understanding
+    /// how much the sketch has grown given the inserted values.
+    pub fn length(&self) -> usize {
+        self.store.length() as usize
+    }
+
+    /// Returns an estimate of the memory used by the sketch in bytes: the size of the struct
+    /// itself plus the heap allocation of its store's bins. The bins grow with the span of keys
+    /// seen, up to the config's `max_num_bins`. A snapshot shares its bins with the sketch it
+    /// was taken from until either is written to, but each reports them in full.
+    pub fn size_bytes(&self) -> usize {
+        core::mem::size_of::<DDSketch>() + self.store.heap_bytes()
+    }
+
+    /// Returns the number of bins holding at least one sample
+    pub fn occupied_bins(&self) -> usize {
+        self.store.bins().count()
+    }
+
+    /// Returns the lowest populated key in the store, or None if sketch is empty
+    pub fn min_key(&self) -> Option<i32> {
+        self.store.bins().next().map(|(key, _)| key)
+    }
+
+    /// Returns the highest populated key in the store, or None if sketch is empty
+    pub fn max_key(&self) -> Option<i32> {
+        self.store.bins().last().map(|(key, _)| key)
+    }
+
+    /// Returns the accuracy and size of the sketch in a single pass over the store, for health
+    /// dashboards
+    pub fn health_report(&self) -> HealthReport {
+        let mut occupied_bins = 0;
+        let mut span = None;
+        for (key, _) in self.store.bins() {
+            occupied_bins += 1;
+            span = Some((span.map_or(key, |(lo, _)| lo), key));
+        }
+        let full = match span {
+            Some((lo, hi)) => hi as i64 - lo as i64 + 1 >= self.config.max_num_bins as i64,
+            None => false,
+        };
+
+        HealthReport {
+            relative_accuracy: self.config.alpha(),
+            occupied_bins,
+            allocated_bins: self.length(),
+            total_count: self.store.count(),
+            is_saturated: self.store.collapse_count > 0 || full,
+        }
+    }
+
+    /// Returns the count, extremes, sum, mean and p50, p90 and p99 of the sketch, finding the
+    /// quantiles in a single pass over the store rather than one per quantile
+    pub fn report(&self) -> Report {
+        let quantiles = self
+            .quantiles(&[0.5, 0.9, 0.99])
+            .expect("report quantiles are within 0.0 to 1.0");
+
+        Report {
+            count: self.count() as u64,
+            min: self.min(),
+            max: self.max(),
+            sum: self.sum(),
+            mean: self.mean(),
+            p50: quantiles[0],
+            p90: quantiles[1],
+            p99: quantiles[2],
+        }
+    }
+
+    /// Returns the number of bins spanned between the keys of the minimum and maximum values
+    /// seen, inclusive, or 0 if the sketch is empty. This reflects the resolution actually in
+    /// use for the recorded range, independent of how much the store has been grown.
+    pub fn span_bins(&self) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+
+        (self.config.key(self.max) - self.config.key(self.min) + 1) as usize
+    }
+
+    /// Returns a rough count of the modes of the distribution, such as the two peaks of a
+    /// latency distribution split between cache hits and misses.
+    ///
+    /// The bins from the lowest to the highest populated key are scanned as a histogram, with
+    /// empty bins counting as zero, and each local maximum is scored by its prominence: its
+    /// height above the higher of the two lowest points separating it from taller bins on either
+    /// side, or its full height if it is the tallest. Every maximum whose prominence is at least
+    /// `min_prominence` times the largest bin count is counted as a peak. Bins are geometric, so
+    /// the modes are those of the distribution of `log(|v|)`, and sparse data may need a
+    /// threshold of 0.2 or more to ignore noise between neighbouring bins.
+    pub fn peak_count(&self, min_prominence: f64) -> usize {
+        let bins: Vec<(i32, u64)> = self.store.bins().collect();
+        let (first, last) = match (bins.first(), bins.last()) {
+            (Some(first), Some(last)) => (first.0, last.0),
+            _ => return 0,
+        };
+
+        let mut counts = vec![0; (last - first + 1) as usize];
+        for (key, count) in bins {
+            counts[(key - first) as usize] = count;
+        }
+        let threshold = min_prominence * *counts.iter().max().unwrap() as f64;
+
+        let mut peaks = 0;
+        let mut i = 0;
+        while i < counts.len() {
+            let h = counts[i];
+            // Treat a plateau of equal counts as a single maximum
+            let mut j = i;
+            while j + 1 < counts.len() && counts[j + 1] == h {
+                j += 1;
+            }
+
+            let rising = i == 0 || counts[i - 1] < h;
+            let falling = j + 1 == counts.len() || counts[j + 1] < h;
+            if rising && falling {
+                // Ties go to the left, so of two equal peaks only the first is the tallest
+                let left = counts[..i].iter().rev().try_fold(h, |col, &c| {
+                    if c >= h {
+                        Err(col)
+                    } else {
+                        Ok(col.min(c))
+                    }
+                });
+                let right = counts[j + 1..].iter().try_fold(h, |col, &c| {
+                    if c > h {
+                        Err(col)
+                    } else {
+                        Ok(col.min(c))
+                    }
+                });
+                // A side reaching the edge without finding a taller bin has no col
+                let col = match (left.err(), right.err()) {
+                    (Some(l), Some(r)) => l.max(r),
+                    (Some(col), None) | (None, Some(col)) => col,
+                    (None, None) => 0,
+                };
+
+                if (h - col) as f64 >= threshold && h > col {
+                    peaks += 1;
+                }
+            }
+
+            i = j + 1;
+        }
+
+        peaks
+    }
+
+    /// Returns the width of the bin that `value` maps to, relative to `value`. This is a concrete
+    /// figure for the sketch's resolution around a given value, and is roughly `2 * alpha` for
+    /// any value above `min_value`. Values within `min_value` of zero share a single bin, so
+    /// their relative width is large, and infinite at zero itself.
+    pub fn resolution_at(&self, value: f64) -> f64 {
+        let key = self.config.key(value);
+
+        (self.config.upper_bound(key) - self.config.lower_bound(key)) / value.abs()
+    }
+
+    /// Returns true if the bins of `o` line up with the bins of this sketch, meaning both map
+    /// values to keys identically and their stores could be combined without re-keying. Unlike
+    /// the config equality required by `merge`, this ignores `max_num_bins`.
+    pub fn is_store_compatible(&self, o: &DDSketch) -> bool {
+        self.config.same_mapping(&o.config)
+    }
+
+    /// Merge the contents of another sketch into this one. The sketch that is merged into this one
+    /// is unchanged after the merge.
+    pub fn merge(&mut self, o: &DDSketch) -> Result<()> {
+        if self.config != o.config {
+            return Err(DDSketchError::Merge);
+        }
+
+        self.merge_unchecked(o);
+
+        Ok(())
+    }
+
+    /// Merge `o` into this sketch like `merge`, unless `key` is already in `seen`, such as when
+    /// an at-least-once pipeline redelivers the same host's sketch. The key is added to `seen`
+    /// once the merge succeeds. Returns whether the merge happened.
+    ///
+    /// Result is an error, represented as DDSketchError::Merge, if the configs differ, in which
+    /// case `key` is not recorded as seen.
+    #[cfg(feature = "std")]
+    pub fn merge_dedup(&mut self, o: &DDSketch, key: u64, seen: &mut HashSet<u64>) -> Result<bool> {
+        if seen.contains(&key) {
+            return Ok(false);
+        }
+
+        self.merge(o)?;
+        seen.insert(key);
+
+        Ok(true)
+    }
+
+    /// Merge another sketch into this one like `merge`, but also accept a sketch whose config
+    /// differs only in `max_num_bins`. The result adopts the larger of the two bin limits, and
+    /// records that its config changed, as reported by `effective_config` and `config_changed`.
+    /// Result is an error, represented as DDSketchError::Merge, if the configs differ in any
+    /// other way.
+    pub fn merge_relaxed(&mut self, o: &DDSketch) -> Result<()> {
+        let max_num_bins = self.config.max_num_bins.max(o.config.max_num_bins);
+        let widened = Config {
+            max_num_bins,
+            ..self.config
+        };
+        if widened
+            != (Config {
+                max_num_bins,
+                ..o.config
+            })
+        {
+            return Err(DDSketchError::Merge);
+        }
+
+        if widened != self.config {
+            self.config = widened;
+            self.store.widen(widened.bin_limit());
+            self.config_changed = true;
+        }
+        self.merge_unchecked(o);
+
+        Ok(())
+    }
+
+    /// Returns the config the sketch currently uses, which `merge_relaxed` may have widened
+    /// from the one it was constructed with
+    pub fn effective_config(&self) -> Config {
+        self.config
+    }
+
+    /// Returns true if both sketches share a config and their quantiles at a fixed set of probe
+    /// points, from 0.01 to 0.99, agree to within the relative `tolerance`. Two empty sketches
+    /// are approximately equal, but an empty sketch never is to a non-empty one.
+    pub fn approx_eq(&self, other: &DDSketch, tolerance: f64) -> bool {
+        if self.config != other.config || self.is_empty() != other.is_empty() {
+            return false;
+        }
+
+        APPROX_EQ_PROBES
+            .iter()
+            .all(|q| match (self.quantile(*q), other.quantile(*q)) {
+                (Ok(Some(a)), Ok(Some(b))) => (a - b).abs() <= tolerance * a.abs().max(b.abs()),
+                _ => true,
+            })
+    }
+
+    /// Returns true if `merge_relaxed` has ever changed the sketch's config
+    pub fn config_changed(&self) -> bool {
+        self.config_changed
+    }
+
+    /// Merge another sketch into this one even if its config differs, by re-binning its
+    /// samples. Each of `o`'s bins is reconstructed as its lower bound, bound by `o`'s extremes,
+    /// and its count added to the bin of that value under this sketch's config. The
+    /// extremes, sum and rejected sample counters are merged exactly. Bins whose value this
+    /// sketch's config can not record, such as non-positive values merged into a log-centered
+    /// sketch, are counted as rejected samples, as `add` would. With identical configs this is
+    /// the same as `merge`.
+    ///
+    /// Re-binning buckets `o`'s samples twice. A bin's lower bound is within `2 * alpha_o` of
+    /// every value in the bin, rather than the `alpha_o` of its representative value, so the
+    /// relative error can compound to `2 * alpha_o + alpha + 2 * alpha_o * alpha`, where
+    /// `alpha_o` and `alpha` are the relative accuracies of `o` and of this sketch. Merging into
+    /// the coarser of two sketches keeps the added error small next to its own.
+    pub fn merge_rebin(&mut self, o: &DDSketch) {
+        if self.config == o.config {
+            self.merge_unchecked(o);
+            return;
+        }
+
+        let mut rebinned = DDSketch::new(self.config);
+        let mut rejected = 0;
+        rebinned.sum = o.sum;
+        for (key, count) in o.store.bins() {
+            let v = o.config.lower_bound(key).max(o.min).min(o.max);
+            if self.config.in_range(v) {
+                rebinned.store.add_n(self.config.key(v), count);
+            } else {
+                rejected += count;
+                rebinned.sum -= v * count as f64;
+            }
+        }
+        rebinned.store.collapse_count += o.store.collapse_count;
+        rebinned.min = o.min;
+        rebinned.max = o.max;
+        rebinned.min_max_trusted = o.min_max_trusted;
+        if rejected > 0 {
+            // Fall back to the extreme bins when an extreme itself was rejected
+            if let (Some(lo), Some(hi)) = (rebinned.min_key(), rebinned.max_key()) {
+                if !self.config.in_range(o.min) {
+                    rebinned.min = self.config.value(lo);
+                    rebinned.min_max_trusted = false;
+                }
+                if !self.config.in_range(o.max) {
+                    rebinned.max = self.config.value(hi);
+                    rebinned.min_max_trusted = false;
+                }
+            }
+            rebinned.reset_if_empty();
+        }
+        rebinned.nan_count = o.nan_count;
+        rebinned.inf_count = o.inf_count;
+        rebinned.out_of_range_count = o.out_of_range_count + rejected;
+
+        self.merge_unchecked(&rebinned);
+    }
+
+    // Merge `o` into this sketch, assuming the configs map values to the same keys
+    fn merge_unchecked(&mut self, o: &DDSketch) {
+        let was_empty = self.store.count() == 0;
+
+        // Merge the stores
+        self.store.merge(&o.store);
+
+        // Need to ensure we don't override min/max with initializers
+        // if either store were empty
+        if was_empty {
+            self.min = o.min;
+            self.max = o.max;
+        } else if o.store.count() > 0 {
+            if o.min < self.min {
+                self.min = o.min
+            }
+            if o.max > self.max {
+                self.max = o.max;
+            }
+        }
+        self.sum += o.sum;
+        self.min_max_trusted &= o.min_max_trusted;
+        self.nan_count += o.nan_count;
+        self.inf_count += o.inf_count;
+        self.out_of_range_count += o.out_of_range_count;
+    }
+
+    /// Merge another sketch into this one by the most exact means its config allows, and report
+    /// which was taken. Sketches whose bins line up are merged exactly as by `merge_relaxed`,
+    /// adopting the larger bin limit. Otherwise, if both separate values from zero at the same
+    /// `min_value` and share the same kind of mapping, `o` is re-binned as by `merge_rebin`,
+    /// losing some accuracy. This lets a fleet of sketches with mixed accuracies be aggregated
+    /// without checking each pair first.
+    ///
+    /// Result is an error, represented as DDSketchError::Merge, if the sketches differ in
+    /// `min_value` or only one of them is log-centered. On error the sketch is unchanged.
+    pub fn smart_merge(&mut self, o: &DDSketch) -> Result<MergeKind> {
+        if self.is_store_compatible(o) && self.merge_relaxed(o).is_ok() {
+            return Ok(MergeKind::Exact);
+        }
+
+        if self.config.min_value != o.config.min_value
+            || self.config.log_centered != o.config.log_centered
+        {
+            return Err(DDSketchError::Merge);
+        }
+
+        self.merge_rebin(o);
+
+        Ok(MergeKind::Folded)
+    }
+
+    /// Merge another sketch into this one like `merge`, and report whether bins were collapsed
+    /// and the resulting worst-case relative error, to monitor the accuracy of a long-lived
+    /// accumulator.
+    ///
+    /// Once bins have been collapsed, at any point in the sketch's lifetime, the lowest bin
+    /// holds every sample from the minimum up to its upper bound, or the highest bin every
+    /// sample from its lower bound up to the maximum under `CollapseStrategy::CollapseHighest`.
+    /// The worst case is then the larger of the relative accuracy and the error of
+    /// reconstructing either end of that range from the bin. It is infinite if that range spans
+    /// zero.
+    pub fn merge_and_report(&mut self, o: &DDSketch) -> Result<MergeReport> {
+        let collapses = self.store.collapse_count;
+        self.merge(o)?;
+
+        Ok(MergeReport {
+            collapsed: self.store.collapse_count > collapses + o.store.collapse_count,
+            bins_after: self.length(),
+            worst_case_rel_error: self.worst_case_rel_error(),
+        })
+    }
+
+    /// Merge another sketch into this one like `merge`, then replace the merged extremes with
+    /// `min_override` and `max_override` where given, for example when the true global extremes
+    /// are known from elsewhere after a lossy aggregation. The overrides are ignored if the
+    /// merged sketch is empty.
+    ///
+    /// Result is an error, represented as DDSketchError::Merge, if the configs differ, or
+    /// DDSketchError::Extremes if the resulting min would exceed the resulting max or either is
+    /// NaN. On error the sketch is unchanged.
+    pub fn merge_with_extremes(
+        &mut self,
+        o: &DDSketch,
+        min_override: Option<f64>,
+        max_override: Option<f64>,
+    ) -> Result<()> {
+        if self.config != o.config {
+            return Err(DDSketchError::Merge);
+        }
+
+        let min = min_override.unwrap_or_else(|| self.min.min(o.min));
+        let max = max_override.unwrap_or_else(|| self.max.max(o.max));
+        let empty = self.is_empty() && o.is_empty();
+        if !empty && (min.is_nan() || max.is_nan() || min > max) {
+            return Err(DDSketchError::Extremes);
+        }
+
+        self.merge_unchecked(o);
+        if !empty {
+            self.min = min;
+            self.max = max;
+        }
+
+        Ok(())
+    }
+
+    /// Merge a batch of sketches on a best-effort basis. The config of the first non-empty sketch
+    /// (or of the first sketch, if all are empty) is used as the reference, every sketch sharing
+    /// it is merged, and the indices of those skipped for having a different config are
+    /// returned alongside the result. The result is None only if `sketches` is empty.
+    pub fn try_merge_all(sketches: &[DDSketch]) -> (Option<DDSketch>, Vec<usize>) {
+        let reference = match sketches
+            .iter()
+            .find(|s| !s.is_empty())
+            .or_else(|| sketches.first())
+        {
+            Some(reference) => reference.config,
+            None => return (None, Vec::new()),
+        };
+
+        let mut merged = DDSketch::new(reference);
+        let mut skipped = Vec::new();
+        for (i, sketch) in sketches.iter().enumerate() {
+            if merged.merge(sketch).is_err() {
+                skipped.push(i);
+            }
+        }
+
+        (Some(merged), skipped)
+    }
+
+    /// Merge another sketch into this one like `merge`, also recording the largest single bin
+    /// count of `o`. Across many calls this tracks the most any one merged source contributed
+    /// to a single bin, reported by `max_source_bin_count`, which helps spot a source that
+    /// dominates a bucket.
+    pub fn merge_tracked(&mut self, o: &DDSketch) -> Result<()> {
+        self.merge(o)?;
+
+        let largest = o.store.bins().map(|(_, count)| count).max().unwrap_or(0);
+        if largest > self.max_source_bin_count {
+            self.max_source_bin_count = largest;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the largest count any single sketch merged with `merge_tracked` contributed to
+    /// one bin, or 0 if nothing has been merged that way.
+    pub fn max_source_bin_count(&self) -> u64 {
+        self.max_source_bin_count
+    }
+
+    /// Drop every bin for which `keep` returns false, given the bin's key and count, for example
+    /// to discard bins below a noise floor. The dropped counts are removed from the total. The
+    /// sum is adjusted from the values reconstructed from the bins, so it becomes approximate.
+    /// The min and max are left as they were, so they become loose bounds if the extreme bins are
+    /// dropped.
+    pub fn retain_bins<F: Fn(i32, u64) -> bool>(&mut self, keep: F) {
+        let config = self.config;
+        let count_before = self.store.count();
+        let (mut removed, mut removed_abs) = (0.0, 0.0);
+        let (mut kept, mut kept_abs) = (0.0, 0.0);
+        self.store.retain(|key, count| {
+            let v = config.value(key) * count as f64;
+            let retain = keep(key, count);
+            if retain {
+                kept += v;
+                kept_abs += v.abs();
+            } else {
+                removed += v;
+                removed_abs += v.abs();
+            }
+            retain
+        });
+
+        // Either subtract the dropped bins from the exact sum or rebuild it from the kept bins,
+        // whichever reconstructs less mass and so carries the smaller error
+        if removed_abs <= kept_abs {
+            self.sum -= removed;
+        } else {
+            self.sum = kept;
+        }
+        if self.store.count() < count_before {
+            self.min_max_trusted = false;
+        }
+        self.reset_if_empty();
+    }
+
+    /// Exponentially decay the sketch by multiplying every bin's count by `factor`, rounding
+    /// down, and scaling the sum by the same factor, so older samples carry less weight than
+    /// those added afterwards. Calling `decay(0.95)` once per interval before adding that
+    /// interval's samples gives a sketch that forgets gradually.
+    ///
+    /// Bins whose counts round down to zero are dropped, so `count` and `sum` become
+    /// approximate after a decay. The min and max are retained and become loose bounds, as
+    /// reported by `extremes_trusted`. Panics if `factor` is not between 0.0 and 1.0, exclusive.
+    pub fn decay(&mut self, factor: f64) {
+        assert!(
+            factor > 0.0 && factor < 1.0,
+            "decay factor must be between 0 and 1"
+        );
+
+        if self.is_empty() {
+            return;
+        }
+
+        self.store.scale_counts(factor);
+        self.sum *= factor;
+        self.min_max_trusted = false;
+        self.reset_if_empty();
+    }
+
+    /// Rebuild the store from only its populated bins into a freshly sized allocation, for
+    /// example after `retain_bins` or `remove` has emptied bins left by transient outliers.
+    /// Besides the empty bins at either end this drops those between populated ones, by packing
+    /// the populated bins alongside their keys whenever that takes less space than the dense
+    /// layout. Counts and summary statistics are unchanged, so every query gives the same
+    /// result as before. Adding or merging samples into the sketch afterwards restores the
+    /// dense layout.
+    pub fn compact(&mut self) {
+        self.store.compact();
+    }
+
+    /// Split the sketch at `value` into the samples below it and the rest, both with this
+    /// sketch's config. Bins with a key below `config.key(value)` go to the first sketch, and
+    /// the others to the second, so the bin straddling `value` is assigned wholly to the second.
+    /// Counts are split exactly and the sums as `retain_bins` adjusts them. The extremes are
+    /// narrowed to the bounds of each side's outermost bins, and the rejected sample counters
+    /// stay with the first sketch, so merging the two gives back this sketch's counts.
+    ///
+    /// Result is an error, represented as DDSketchError::Value, if `value` is NaN.
+    pub fn split_at(&self, value: f64) -> Result<(DDSketch, DDSketch)> {
+        if value.is_nan() {
+            return Err(DDSketchError::Value);
+        }
+
+        let key = self.config.key(value);
+        let mut below = self.clone();
+        below.retain_bins(|k, _| k < key);
+        if let Some(k) = below.max_key() {
+            below.max = below.max.min(self.config.upper_bound(k));
+        }
+
+        let mut above = self.clone();
+        above.retain_bins(|k, _| k >= key);
+        if let Some(k) = above.min_key() {
+            above.min = above.min.max(self.config.lower_bound(k));
+        }
+        above.nan_count = 0;
+        above.inf_count = 0;
+        above.out_of_range_count = 0;
+
+        Ok((below, above))
+    }
+
+    /// Multiply every value recorded in the sketch by `factor`, for example to convert units
+    /// after the fact. Since the bins are geometric this only shifts each key by
+    /// `round(log_gamma(|factor|))`, so no samples need to be re-ingested. The min, max and sum
+    /// are scaled exactly.
+    ///
+    /// Unless `factor` is a power of gamma the shift rounds to the nearest bin, which can add up
+    /// to half a bin of error on top of the sketch's relative accuracy. Values scaled below
+    /// `min_value` collapse into the zero bin. A negative factor mirrors the sketch around zero,
+    /// and a zero factor moves every sample to zero. Non-finite factors are ignored, as are
+    /// non-positive ones for a log-centered sketch, whose bins can only shift.
+    pub fn scale(&mut self, factor: f64) {
+        if !factor.is_finite() || (self.config.log_centered && factor <= 0.0) {
+            return;
+        }
+        self.scale_factor *= factor;
+
+        if factor == 0.0 {
+            let count = self.store.count();
+            self.store = self.store.remap(|_| 0);
+            if count > 0 {
+                self.min = 0.0;
+                self.max = 0.0;
+            }
+            self.sum = 0.0;
+            return;
+        }
+
+        let shift = self.config.log_gamma(factor.abs()).round() as i32;
+        let mirror = factor < 0.0;
+        let log_centered = self.config.log_centered;
+        self.store = self.store.remap(|key| {
+            let key = if log_centered {
+                key + shift
+            } else if key > 0 {
+                (key + shift).max(0)
+            } else if key < 0 {
+                (key - shift).min(0)
+            } else {
+                0
+            };
+            if mirror {
+                -key
+            } else {
+                key
+            }
+        });
+
+        if !self.is_empty() {
+            let (min, max) = (self.min * factor, self.max * factor);
+            self.min = min.min(max);
+            self.max = min.max(max);
+        }
+        self.sum *= factor;
+    }
+
+    /// Return a new sketch with the same config where every recorded value is transformed by `f`,
+    /// for example to add a constant offset. Each bin's representative value is mapped through
+    /// `f` and re-keyed, and the min and max are mapped exactly. The sum is recomputed from the
+    /// mapped bins, so it becomes approximate.
+    ///
+    /// `f` must be monotone for the result to be meaningful: otherwise bins may be reordered and
+    /// quantiles of the result are undefined. Bins mapped to a non-finite or out of range value
+    /// are counted as rejected samples, as `add` would.
+    pub fn map_monotone<F: Fn(f64) -> f64>(&self, f: F) -> DDSketch {
+        let mut mapped = DDSketch::new(self.config);
+        mapped.nan_count = self.nan_count;
+        mapped.inf_count = self.inf_count;
+        mapped.out_of_range_count = self.out_of_range_count;
+
+        for (key, count) in self.store.bins() {
+            let v = f(self.config.value(key));
+            if v.is_nan() {
+                mapped.nan_count += count;
+            } else if v.is_infinite() {
+                mapped.inf_count += count;
+            } else if !self.config.in_range(v) {
+                mapped.out_of_range_count += count;
+            } else {
+                mapped.store.add_n(self.config.key(v), count);
+                mapped.sum += v * count as f64;
+            }
+        }
+
+        if !mapped.is_empty() {
+            // Fall back to the extreme bins when an extreme itself maps to a rejected value
+            let (min, max) = (f(self.min), f(self.max));
+            let (lo, hi) = (min.min(max), min.max(max));
+            let accepted = |v: f64| v.is_finite() && self.config.in_range(v);
+            let mut bins = mapped.store.bins();
+            let first = bins.next().map(|(key, _)| self.config.value(key));
+            let last = bins.last().map(|(key, _)| self.config.value(key)).or(first);
+            mapped.min = if accepted(lo) { lo } else { first.unwrap() };
+            mapped.max = if accepted(hi) { hi } else { last.unwrap() };
+            mapped.min_max_trusted = self.min_max_trusted && accepted(lo) && accepted(hi);
+        }
+
+        mapped
+    }
+
+    /// Return a new sketch of the magnitudes of the recorded values, where each bin of negative
+    /// values is folded onto the bin of its positive mirror. This suits data that should be
+    /// symmetric around zero, when only the size of a deviation matters.
+    ///
+    /// If every value has the same sign the extremes and sum carry over exactly. Otherwise the
+    /// largest magnitude is still exact, but the smallest one and the sum are recomputed from
+    /// the folded bins, so they become approximate.
+    pub fn abs(&self) -> DDSketch {
+        let mut folded = self.clone();
+        if self.is_empty() || self.min >= 0.0 {
+            return folded;
+        }
+
+        folded.store = self.store.remap(|key| key.abs());
+        if self.max <= 0.0 {
+            folded.min = -self.max;
+            folded.max = -self.min;
+            folded.sum = -self.sum;
+        } else {
+            let max = self.max.max(-self.min);
+            let config = self.config;
+            let value = |key| config.value(key).max(0.0).min(max);
+            folded.max = max;
+            folded.min = folded
+                .store
+                .bins()
+                .next()
+                .map_or(0.0, |(key, _)| value(key));
+            folded.sum = folded
+                .store
+                .bins()
+                .map(|(key, count)| value(key) * count as f64)
+                .sum();
+            folded.min_max_trusted = false;
+        }
+
+        folded
+    }
+
+    /// Iterate over the non-empty bins in ascending key order, yielding each bin's lower bound
+    /// from `Config::lower_bound` and its count. Accumulating the counts gives a cumulative
+    /// histogram, such as for exporting to Prometheus.
+    pub fn bins(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        self.store
+            .bins()
+            .map(move |(key, count)| (self.config.lower_bound(key), count))
+    }
+
+    /// Iterate over approximate raw samples, yielding `count` copies of each bin's representative
+    /// value in ascending order, bound by the extremes. Feeding these into a sketch with another
+    /// config re-sketches this one, at the cost of compounding both sketches' relative errors.
+    ///
+    /// The samples are produced lazily, but one is yielded for every sample in the sketch, so
+    /// iterating takes time proportional to `count()` rather than the number of bins.
+    pub fn approx_samples(&self) -> impl Iterator<Item = f64> + '_ {
+        self.store.bins().flat_map(move |(key, count)| {
+            let v = self.config.value(key).max(self.min).min(self.max);
+            core::iter::repeat_n(v, count as usize)
+        })
+    }
+
+    /// Return a point in time copy of the sketch for querying while samples keep being added to
+    /// this one. The bins are shared with this sketch until either is next written to, so taking
+    /// a snapshot is cheap; only the first write after it copies the bins. Wrap the snapshot
+    /// with `into_shared` to hand it to many readers.
+    pub fn snapshot(&self) -> DDSketch {
+        self.clone()
+    }
+
+    /// Return a hash of the sketch's config, populated bins and extremes. How the store is laid
+    /// out does not affect it, so this is a cheap way to tell whether a sketch changed between
+    /// two observations, and sketches that compare equal have the same fingerprint. The hash is
+    /// FNV-1a, which is stable across builds and platforms.
+    ///
+    /// The sum is left out, since floating point addition depends on the order of its operands:
+    /// sketches given the same samples in a different order have the same fingerprint even when
+    /// their sums differ in the last bits. The scale factor and rejected sample counters are
+    /// left out too, as they are by `==`.
+    pub fn fingerprint(&self) -> u64 {
+        let c = &self.config;
+        let mut hash = FNV_OFFSET_BASIS;
+        for word in &[
+            c.max_num_bins as u64,
+            c.gamma.to_bits(),
+            c.min_value.to_bits(),
+            c.offset as u64,
+            c.max_value.to_bits(),
+            (c.collapse == CollapseStrategy::CollapseHighest) as u64,
+            c.log_centered as u64,
+            // Adding zero folds -0.0 into 0.0
+            (self.min + 0.0).to_bits(),
+            (self.max + 0.0).to_bits(),
+        ] {
+            hash = fnv1a(hash, *word);
+        }
+        for (key, count) in self.store.bins() {
+            hash = fnv1a(hash, key as u64);
+            hash = fnv1a(hash, count);
+        }
+
+        hash
+    }
+
+    /// Compute the changes needed to turn `baseline` into this sketch. Only bins whose counts
+    /// differ are recorded, so the diff stays small when a long-lived sketch has only seen a
+    /// few new samples since the baseline was taken. Both sketches must share the same config.
+    pub fn diff_since(&self, baseline: &DDSketch) -> Result<SketchDiff> {
+        if self.config != baseline.config {
+            return Err(DDSketchError::Merge);
+        }
+
+        let mut bins = Vec::new();
+        let mut current = self.store.bins().peekable();
+        let mut previous = baseline.store.bins().peekable();
+
+        loop {
+            let (key, delta) = match (current.peek(), previous.peek()) {
+                (Some(&(ck, cn)), Some(&(pk, pn))) => {
+                    if ck == pk {
+                        current.next();
+                        previous.next();
+                        (ck, cn as i64 - pn as i64)
+                    } else if ck < pk {
+                        current.next();
+                        (ck, cn as i64)
+                    } else {
+                        previous.next();
+                        (pk, -(pn as i64))
+                    }
+                }
+                (Some(&(ck, cn)), None) => {
+                    current.next();
+                    (ck, cn as i64)
+                }
+                (None, Some(&(pk, pn))) => {
+                    previous.next();
+                    (pk, -(pn as i64))
+                }
+                (None, None) => break,
+            };
+
+            if delta != 0 {
+                bins.push((key, delta));
+            }
+        }
+
+        Ok(SketchDiff {
+            config: self.config,
+            bins,
+            min: self.min,
+            max: self.max,
+            min_max_trusted: self.min_max_trusted,
+            sum: self.sum - baseline.sum,
+            nan_count: self.nan_count as i64 - baseline.nan_count as i64,
+            inf_count: self.inf_count as i64 - baseline.inf_count as i64,
+            out_of_range_count: self.out_of_range_count as i64 - baseline.out_of_range_count as i64,
+        })
+    }
+
+    /// Replay a diff produced by `diff_since` on top of the baseline it was computed against.
+    /// The min and max, and whether they are exact, are taken as-is from the diff, so applying
+    /// it to any sketch other than its baseline yields extremes that may not match the bins.
+    pub fn apply_diff(&mut self, diff: &SketchDiff) -> Result<()> {
+        if self.config != diff.config {
+            return Err(DDSketchError::Merge);
+        }
+
+        for &(key, delta) in &diff.bins {
+            if delta > 0 {
+                self.store.add_n(key, delta as u64);
+            } else {
+                self.store.remove_n(key, delta.unsigned_abs());
+            }
+        }
+
+        self.min = diff.min;
+        self.max = diff.max;
+        self.min_max_trusted = diff.min_max_trusted;
+        self.sum += diff.sum;
+        self.nan_count = self.nan_count.saturating_add_signed(diff.nan_count);
+        self.inf_count = self.inf_count.saturating_add_signed(diff.inf_count);
+        self.out_of_range_count = self
+            .out_of_range_count
+            .saturating_add_signed(diff.out_of_range_count);
+
+        Ok(())
+    }
+
+    // After a lossy removal empties the sketch, drop extremes and sum that no longer describe
+    // any samples, so they do not leak into later additions
+    fn reset_if_empty(&mut self) {
+        if self.is_empty() {
+            self.min = f64::INFINITY;
+            self.max = -f64::INFINITY;
+            self.sum = 0.0;
+            self.min_max_trusted = true;
+        }
+    }
+
+    // The worst relative error of a reconstructed sample, accounting for a collapsed bin at the
+    // end of the key range given by the collapse strategy
+    fn worst_case_rel_error(&self) -> f64 {
+        let alpha = self.config.alpha();
+        if self.store.collapse_count == 0 || self.is_empty() {
+            return alpha;
+        }
+
+        let (key, lo, hi) = match self.config.collapse {
+            CollapseStrategy::CollapseLowest => {
+                let key = self.store.key_at_rank(1);
+                (key, self.min, self.config.upper_bound(key).min(self.max))
+            }
+            CollapseStrategy::CollapseHighest => {
+                let key = self.store.key_at_rank(self.store.count());
+                (key, self.config.lower_bound(key).max(self.min), self.max)
+            }
+        };
+        let rep = self.config.value(key).max(self.min).min(self.max);
+        if lo < 0.0 && hi > 0.0 {
+            return f64::INFINITY;
+        }
+
+        [lo, hi]
+            .iter()
+            .map(|&v| {
+                if v == 0.0 {
+                    if rep == 0.0 {
+                        0.0
+                    } else {
+                        f64::INFINITY
+                    }
+                } else {
+                    (rep - v).abs() / v.abs()
+                }
+            })
+            .fold(alpha, f64::max)
+    }
+
+    // Return `bound` of the bin holding the quantile for `q`, bound by the extremes
+    fn bin_bound_quantile(&self, q: f64, bound: fn(&Config, i32) -> f64) -> Result<Option<f64>> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(DDSketchError::Quantile);
+        }
+
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        if q == 0.0 {
+            return Ok(Some(self.min));
+        } else if q == 1.0 {
+            return Ok(Some(self.max));
+        }
+
+        let rank = (q * ((self.count() - 1) as f64) + 1.0) as u64;
+        let key = self.store.key_at_rank(rank);
+
+        Ok(Some(bound(&self.config, key).max(self.min).min(self.max)))
+    }
+
+    // Reconstruct the value of the given 1-based rank, bound by the extremes
+    fn value_at_rank(&self, rank: u64) -> f64 {
+        if let Some(v) = self.single_bin_value() {
+            return v;
+        }
+        let key = self.store.key_at_rank(rank);
+
+        self.config.value(key).max(self.min).min(self.max)
+    }
+
+    // Reconstruct the values of the given ascending 1-based ranks in a single pass over the
+    // store, bound by the extremes
+    fn values_at_ranks(&self, ranks: &[u64]) -> Vec<f64> {
+        if let Some(v) = self.single_bin_value() {
+            return vec![v; ranks.len()];
+        }
+
+        self.store
+            .keys_at_ranks(ranks)
+            .into_iter()
+            .map(|key| self.config.value(key).max(self.min).min(self.max))
+            .collect()
+    }
+
+    // When the extremes share a bin every sample does, which is common for constant metrics,
+    // so every rank has that bin's value and there is no need to search the store for it
+    fn single_bin_value(&self) -> Option<f64> {
+        let key = self.config.key(self.min);
+        if key != self.config.key(self.max) {
+            return None;
+        }
+
+        Some(self.config.value(key).max(self.min).min(self.max))
+    }
+}
+
+/// Summarizes the sketch by its count, extremes, sum, number of populated bins and relative
+/// accuracy, rather than listing every bin
+impl fmt::Debug for DDSketch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DDSketch")
+            .field("count", &self.count())
+            .field("min", &self.min())
+            .field("max", &self.max())
+            .field("sum", &self.sum())
+            .field("bins", &self.store.bins().count())
+            .field("alpha", &self.config.alpha())
+            .finish()
+    }
+}
+
+/// Sketches are equal when they share a config and hold the same bins, extremes and sum,
+/// regardless of how their stores are laid out. Rejected sample counters are not compared.
+impl PartialEq for DDSketch {
+    fn eq(&self, o: &DDSketch) -> bool {
+        self.config == o.config
+            && self.min == o.min
+            && self.max == o.max
+            && self.sum == o.sum
+            && self.store.bins().eq(o.store.bins())
+    }
+}
+
+/// An empty sketch built with `Config::defaults`
+impl Default for DDSketch {
+    fn default() -> Self {
+        DDSketch::new(Config::default())
+    }
+}
+
+/// Collect the samples into a sketch built with `Config::defaults`. Use `extend` on a sketch
+/// constructed with `DDSketch::new` to choose a different config.
+impl core::iter::FromIterator<f64> for DDSketch {
+    fn from_iter<I: IntoIterator<Item = f64>>(iter: I) -> Self {
+        let mut sketch = DDSketch::new(Config::defaults());
+        sketch.extend(iter);
+        sketch
+    }
+}
+
+/// Add every sample to the sketch, as `add` would
+impl Extend<f64> for DDSketch {
+    fn extend<I: IntoIterator<Item = f64>>(&mut self, iter: I) {
+        for v in iter {
+            self.add(v);
+        }
+    }
+}
+
+/// Merge `o` into the sketch, as `merge` would. Panics if the configs differ; use `merge` to
+/// handle that as an error.
+impl core::ops::AddAssign<&DDSketch> for DDSketch {
+    fn add_assign(&mut self, o: &DDSketch) {
+        self.merge(o)
+            .expect("can not merge sketches with different configs");
+    }
+}
+
+/// Return a new sketch merging both operands, leaving them unchanged. Result is an error,
+/// represented as DDSketchError::Merge, if the configs differ.
+impl core::ops::Add<&DDSketch> for &DDSketch {
+    type Output = Result<DDSketch>;
+
+    fn add(self, o: &DDSketch) -> Result<DDSketch> {
+        let mut merged = self.clone();
+        merged.merge(o)?;
+        Ok(merged)
+    }
+}
+
+/// Return the quantile value for `q` over the union of `sketches`, where each non-empty sketch
+/// carries the same total weight regardless of how many samples it holds. A plain merge weights
+/// each sketch by its count; this instead normalizes every sketch's bins to sum to one before
+/// ranking, so a tiny sketch influences the result as much as a huge one.
+///
+/// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to 1.0,
+/// or DDSketchError::Merge if the sketches do not all share the same config. If every sketch is
+/// empty the result is None.
+pub fn equal_weight_quantile(sketches: &[&DDSketch], q: f64) -> Result<Option<f64>> {
+    if !(0.0..=1.0).contains(&q) {
+        return Err(DDSketchError::Quantile);
+    }
+    if let Some(first) = sketches.first() {
+        if sketches.iter().any(|s| s.config != first.config) {
+            return Err(DDSketchError::Merge);
+        }
+    }
+
+    let non_empty: Vec<&DDSketch> = sketches.iter().copied().filter(|s| !s.is_empty()).collect();
+    if non_empty.is_empty() {
+        return Ok(None);
+    }
+
+    let min = non_empty
+        .iter()
+        .map(|s| s.min)
+        .fold(f64::INFINITY, f64::min);
+    let max = non_empty
+        .iter()
+        .map(|s| s.max)
+        .fold(-f64::INFINITY, f64::max);
+    if q == 0.0 {
+        return Ok(Some(min));
+    } else if q == 1.0 {
+        return Ok(Some(max));
+    }
+
+    let mut weights: Vec<(i32, f64)> = non_empty
+        .iter()
+        .flat_map(|s| {
+            let total = s.store.count() as f64;
+            s.store
+                .bins()
+                .map(move |(key, count)| (key, count as f64 / total))
+        })
+        .collect();
+    weights.sort_by_key(|(key, _)| *key);
+
+    let target = q * non_empty.len() as f64;
+    let mut n = 0.0;
+    let mut key = weights[weights.len() - 1].0;
+    for (k, w) in weights {
+        n += w;
+        if n >= target {
+            key = k;
+            break;
+        }
+    }
+
+    let config = &non_empty[0].config;
+    Ok(Some(config.value(key).max(min).min(max)))
+}
+
+/// Return a sketch of the average distribution of `sketches`, such as the typical latency
+/// profile of a host across a fleet. Where a merge sums the sketches so that larger ones
+/// dominate, this normalizes each non-empty sketch's bins to fractions of its count and averages
+/// the fractions bin by bin, so every sketch contributes its shape equally. The result holds
+/// the mean count of the non-empty sketches, rounded, spread over the averaged bins, with a sum
+/// to match the mean of their means. Its extremes are those of the union.
+///
+/// Result is an error, represented as DDSketchError::Merge, if the sketches do not all share
+/// the same config. If every sketch is empty the result is an empty sketch with their config,
+/// or with `Config::defaults` if there are none.
+pub fn average_distribution(sketches: &[&DDSketch]) -> Result<DDSketch> {
+    let config = match sketches.first() {
+        Some(first) => first.config,
+        None => return Ok(DDSketch::default()),
+    };
+    if sketches.iter().any(|s| s.config != config) {
+        return Err(DDSketchError::Merge);
+    }
+
+    let mut averaged = DDSketch::new(config);
+    let non_empty: Vec<&DDSketch> = sketches.iter().copied().filter(|s| !s.is_empty()).collect();
+    if non_empty.is_empty() {
+        return Ok(averaged);
+    }
+
+    let m = non_empty.len() as f64;
+    let mut weights: Vec<(i32, f64)> = non_empty
+        .iter()
+        .flat_map(|s| {
+            let total = s.store.count() as f64;
+            s.store
+                .bins()
+                .map(move |(key, count)| (key, count as f64 / total))
+        })
+        .collect();
+    weights.sort_by_key(|(key, _)| *key);
+
+    // Rounding the running total rather than each bin keeps the bins summing to the mean count
+    let total = (non_empty.iter().map(|s| s.store.count()).sum::<u64>() as f64 / m).round();
+    let mut cumulative = 0.0;
+    let mut placed = 0;
+    let mut i = 0;
+    while i < weights.len() {
+        let key = weights[i].0;
+        while i < weights.len() && weights[i].0 == key {
+            cumulative += weights[i].1;
+            i += 1;
+        }
+        let upto = ((cumulative / m * total).round() as u64).min(total as u64);
+        if upto > placed {
+            averaged.store.add_n(key, upto - placed);
+            placed = upto;
+        }
+    }
+
+    let mean = non_empty
+        .iter()
+        .map(|s| s.sum / s.count() as f64)
+        .sum::<f64>()
+        / m;
+    averaged.min = non_empty
+        .iter()
+        .map(|s| s.min)
+        .fold(f64::INFINITY, f64::min);
+    averaged.max = non_empty
+        .iter()
+        .map(|s| s.max)
+        .fold(-f64::INFINITY, f64::max);
+    averaged.sum = mean * averaged.store.count() as f64;
+    averaged.min_max_trusted = non_empty.iter().all(|s| s.min_max_trusted);
+
+    Ok(averaged)
+}
+
+/// Return the `(min, median, max)` of the quantile values for `q` of each sketch in `series`,
+/// for example to draw a band across a time series of per-minute sketches. Empty sketches are
+/// skipped, and with an even number of values the median is the mean of the middle two.
+///
+/// Result is an error, represented as DDSketchError::Quantile, if `q` is outside of 0.0 to 1.0,
+/// or DDSketchError::Merge if the sketches do not all share the same config. If every sketch is
+/// empty the result is None.
+pub fn quantile_band(series: &[&DDSketch], q: f64) -> Result<Option<(f64, f64, f64)>> {
+    if !(0.0..=1.0).contains(&q) {
+        return Err(DDSketchError::Quantile);
+    }
+    if let Some(first) = series.first() {
+        if series.iter().any(|s| s.config != first.config) {
+            return Err(DDSketchError::Merge);
+        }
+    }
+
+    let mut values = Vec::with_capacity(series.len());
+    for sketch in series {
+        if let Some(v) = sketch.quantile(q)? {
+            values.push(v);
+        }
+    }
+    if values.is_empty() {
+        return Ok(None);
+    }
+
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = values.len() / 2;
+    let median = if values.len() % 2 == 1 {
+        values[mid]
+    } else {
+        (values[mid - 1] + values[mid]) / 2.0
+    };
+
+    Ok(Some((values[0], median, values[values.len() - 1])))
+}
+
+const APPROX_EQ_PROBES: [f64; 9] = [0.01, 0.05, 0.1, 0.25, 0.5, 0.75, 0.9, 0.95, 0.99];
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+// Fold the little endian bytes of `word` into an FNV-1a hash
+fn fnv1a(mut hash: u64, word: u64) -> u64 {
+    for b in &word.to_le_bytes() {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// Return z such that a standard normal variable exceeds z with probability `p`, for p in
+// (0, 0.5], using the rational approximation from Abramowitz and Stegun 26.2.23, which is
+// accurate to within 4.5e-4.
+fn normal_critical_value(p: f64) -> f64 {
+    let t = (-2.0 * p.ln()).sqrt();
+
+    t - (2.515517 + 0.802853 * t + 0.010328 * t * t)
+        / (1.0 + 1.432788 * t + 0.189269 * t * t + 0.001308 * t * t * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+    #[cfg(feature = "std")]
+    use std::collections::HashSet;
+
+    use crate::DDSketch;
+    use crate::{average_distribution, equal_weight_quantile, quantile_band};
+    use crate::{AddOutcome, DDSketchError, MergeKind};
+    use crate::{CollapseStrategy, Config, Provenance};
+
+    #[test]
+    fn test_simple_quantile() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..101 {
+            dd.add(i as f64);
+        }
+
+        assert_eq!(dd.quantile(0.95).unwrap().unwrap().ceil(), 95.0);
+
+        assert!(dd.quantile(-1.01).is_err());
+        assert!(dd.quantile(1.01).is_err());
+    }
+
+    #[test]
+    fn test_empty_sketch() {
+        let c = Config::defaults();
+        let dd = DDSketch::new(c);
+
+        assert_eq!(dd.quantile(0.98).unwrap(), None);
+        assert_eq!(dd.max(), None);
+        assert_eq!(dd.min(), None);
+        assert_eq!(dd.sum(), None);
+        assert_eq!(dd.count(), 0);
+
+        assert!(dd.quantile(1.01).is_err());
+    }
+
+    #[test]
+    fn test_basic_histogram_data() {
+        let values = &[
+            0.754225035,
+            0.752900282,
+            0.752812246,
+            0.752602367,
             0.754310155,
             0.753525981,
             0.752981082,
@@ -262,17 +2486,2420 @@ mod tests {
         let c = Config::defaults();
         let mut dd = DDSketch::new(c);
 
-        for value in values {
-            dd.add(*value);
+        for value in values {
+            dd.add(*value);
+        }
+
+        assert_eq!(dd.max(), Some(0.755188464));
+        assert_eq!(dd.min(), Some(0.750064549));
+        assert_eq!(dd.count(), 31);
+        assert_eq!(dd.sum(), Some(23.343630625000003));
+
+        assert!(dd.quantile(0.25).unwrap().is_some());
+        assert!(dd.quantile(0.5).unwrap().is_some());
+        assert!(dd.quantile(0.75).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_quantile_with_ci() {
+        let c = Config::defaults();
+        let mut large = DDSketch::new(c);
+        let mut small = DDSketch::new(c);
+
+        for i in 1..10001 {
+            large.add(i as f64);
+            if i % 100 == 0 {
+                small.add(i as f64);
+            }
+        }
+
+        let (lo, point, hi) = large.quantile_with_ci(0.9, 0.95).unwrap().unwrap();
+        assert!(lo <= point && point <= hi);
+        assert!(lo < 9000.0 && 9000.0 < hi);
+
+        let (small_lo, small_point, small_hi) = small.quantile_with_ci(0.9, 0.95).unwrap().unwrap();
+        assert!(small_lo <= small_point && small_point <= small_hi);
+        assert!(small_hi - small_lo > hi - lo);
+
+        assert!(large.quantile_with_ci(0.9, 1.0).is_err());
+        assert!(large.quantile_with_ci(1.1, 0.95).is_err());
+        assert_eq!(DDSketch::new(c).quantile_with_ci(0.5, 0.95).unwrap(), None);
+    }
+
+    #[test]
+    fn test_tail_quantiles() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        assert!(dd.tail_quantiles(3).is_empty());
+
+        for i in 1..100_001 {
+            dd.add(i as f64);
+        }
+
+        let tails = dd.tail_quantiles(5);
+        assert_eq!(tails.len(), 5);
+        for (q, value) in &tails {
+            assert_eq!(Some(*value), dd.quantile(*q).unwrap());
+        }
+        for pair in tails.windows(2) {
+            assert!(pair[0].0 < pair[1].0);
+            assert!(pair[0].1 <= pair[1].1);
+        }
+
+        let (_, last) = tails[4];
+        assert!((dd.max().unwrap() - last) / last <= 0.01);
+    }
+
+    #[test]
+    fn test_constant_values_agree() {
+        let c = Config::defaults();
+
+        for v in &[0.1, 42.0, -3.3, 1.0e-3, 0.0] {
+            let mut dd = DDSketch::new(c);
+            for _ in 0..10 {
+                dd.add(*v);
+            }
+
+            assert_eq!(dd.min(), Some(*v));
+            assert_eq!(dd.max(), Some(*v));
+            assert_eq!(dd.mean(), Some(*v));
+            for q in &[0.0, 0.01, 0.25, 0.5, 0.75, 0.99, 1.0] {
+                assert_eq!(dd.quantile(*q).unwrap(), Some(*v));
+            }
+        }
+    }
+
+    #[test]
+    fn test_winsorized_mean() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.mean(), None);
+        assert_eq!(dd.winsorized_mean(0.95).unwrap(), None);
+
+        for i in 1..100 {
+            dd.add(i as f64);
+        }
+        dd.add(1.0e6);
+
+        let raw = dd.mean().unwrap();
+        let winsorized = dd.winsorized_mean(0.95).unwrap().unwrap();
+
+        assert!(raw > 10_000.0);
+        assert!((winsorized - 50.0).abs() < 2.0, "{}", winsorized);
+        assert_eq!(dd.winsorized_mean(0.05).unwrap(), Some(winsorized));
+        assert!(dd.winsorized_mean(1.5).is_err());
+    }
+
+    #[test]
+    fn test_resolution_at() {
+        let alpha = 0.01;
+        let c = Config::new(alpha, 2048, 1.0e-9);
+        let dd = DDSketch::new(c);
+
+        for v in &[1.0e-6, 0.25, 1.0, 250.0, 1.0e6, -250.0] {
+            let r = dd.resolution_at(*v);
+            assert!((r - 2.0 * alpha).abs() < 0.05 * 2.0 * alpha, "{}: {}", v, r);
+        }
+
+        assert!(dd.resolution_at(0.0).is_infinite());
+    }
+
+    #[test]
+    fn test_is_store_compatible() {
+        let d1 = DDSketch::new(Config::new(0.01, 2048, 1.0e-9));
+        let d2 = DDSketch::new(Config::new(0.01, 512, 1.0e-9));
+        let d3 = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+        let d4 = DDSketch::new(Config::new(0.01, 2048, 1.0e-6));
+
+        assert!(d1.is_store_compatible(&d2));
+        assert!(d2.is_store_compatible(&d1));
+        assert!(!d1.is_store_compatible(&d3));
+        assert!(!d1.is_store_compatible(&d4));
+
+        let mut d1 = d1;
+        assert!(d1.merge(&d2).is_err());
+    }
+
+    #[test]
+    fn test_average_distribution() {
+        let c = Config::defaults();
+        let mut small = DDSketch::new(c);
+        let mut large = DDSketch::new(c);
+        for i in 1..=100 {
+            small.add(i as f64);
+            large.add_n(i as f64, 10);
+        }
+
+        // Both have the same shape, so their average does too
+        let avg = average_distribution(&[&small, &large, &DDSketch::new(c)]).unwrap();
+        assert_eq!(avg.count(), 550);
+        assert_eq!(avg.min(), Some(1.0));
+        assert_eq!(avg.max(), Some(100.0));
+        assert!((avg.mean().unwrap() - 50.5).abs() < 1.0e-9);
+        for q in &[0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0] {
+            assert_eq!(avg.quantile(*q).unwrap(), small.quantile(*q).unwrap());
+        }
+
+        // A union would be dominated by the larger sketch's high values
+        let mut high = DDSketch::new(c);
+        high.add_n(1000.0, 1000);
+        let avg = average_distribution(&[&small, &high]).unwrap();
+        let median = avg.quantile(0.5).unwrap().unwrap();
+        assert!(
+            (median - 100.0).abs() <= 100.0 * c.alpha(),
+            "median {}",
+            median
+        );
+
+        assert!(average_distribution(&[]).unwrap().is_empty());
+        let other = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+        assert!(average_distribution(&[&small, &other]).is_err());
+    }
+
+    #[test]
+    fn test_equal_weight_quantile() {
+        let c = Config::defaults();
+        let mut tiny = DDSketch::new(c);
+        let mut huge = DDSketch::new(c);
+
+        for _ in 0..10 {
+            tiny.add(1.0);
+        }
+        for _ in 0..10_000 {
+            huge.add(100.0);
+        }
+
+        let mut merged = tiny.clone();
+        merged.merge(&huge).unwrap();
+        assert!((merged.quantile(0.25).unwrap().unwrap() - 100.0).abs() <= 1.0);
+
+        let sketches = [&tiny, &huge];
+        let lo = equal_weight_quantile(&sketches, 0.25).unwrap().unwrap();
+        let hi = equal_weight_quantile(&sketches, 0.75).unwrap().unwrap();
+        assert!((lo - 1.0).abs() <= 0.01);
+        assert!((hi - 100.0).abs() <= 1.0);
+        assert_eq!(equal_weight_quantile(&sketches, 0.0).unwrap(), Some(1.0));
+
+        let empty = DDSketch::new(c);
+        assert_eq!(equal_weight_quantile(&[&empty], 0.5).unwrap(), None);
+        assert!(matches!(
+            equal_weight_quantile(&sketches, 1.5),
+            Err(DDSketchError::Quantile)
+        ));
+
+        let other = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+        assert!(matches!(
+            equal_weight_quantile(&[&tiny, &other], 0.5),
+            Err(DDSketchError::Merge)
+        ));
+    }
+
+    #[test]
+    fn test_try_merge_all() {
+        let c1 = Config::defaults();
+        let c2 = Config::new(0.02, 2048, 1.0e-9);
+
+        let mut sketches = Vec::new();
+        sketches.push(DDSketch::new(c2));
+        for (i, c) in [c1, c2, c1, c1, c2].iter().enumerate() {
+            let mut dd = DDSketch::new(*c);
+            for v in 0..(i + 1) {
+                dd.add(v as f64);
+            }
+            sketches.push(dd);
+        }
+
+        let (merged, skipped) = DDSketch::try_merge_all(&sketches);
+        assert_eq!(skipped, vec![0, 2, 5]);
+        assert_eq!(merged.unwrap().count(), 1 + 3 + 4);
+
+        let (merged, skipped) = DDSketch::try_merge_all(&[]);
+        assert!(merged.is_none());
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_merge_tracked() {
+        let c = Config::defaults();
+        let mut total = DDSketch::new(c);
+
+        for source in 0..5 {
+            let mut dd = DDSketch::new(c);
+            for i in 1..11 {
+                dd.add(i as f64);
+            }
+            if source == 3 {
+                for _ in 0..40 {
+                    dd.add(5.0);
+                }
+            }
+            total.merge_tracked(&dd).unwrap();
+        }
+
+        assert_eq!(total.count(), 90);
+        assert_eq!(total.max_source_bin_count(), 41);
+
+        let other = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+        assert!(total.merge_tracked(&other).is_err());
+        assert_eq!(total.max_source_bin_count(), 41);
+    }
+
+    #[test]
+    fn test_nonzero_count() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.nonzero_count(), 0);
+
+        for i in 0..30 {
+            dd.add(if i % 3 == 0 { 0.0 } else { i as f64 - 15.0 });
+        }
+        dd.add(1.0e-12);
+
+        // 10 explicit zeros and 1 value below min_value
+        assert_eq!(dd.zero_count(), 11);
+        assert_eq!(dd.nonzero_count(), 20);
+    }
+
+    #[test]
+    fn test_zero_bin_fraction() {
+        // Microsecond latencies recorded in seconds, with a min_value of a millisecond
+        let c = Config::new(0.01, 2048, 1.0e-3);
+        let mut dd = DDSketch::new(c);
+        assert_eq!(dd.zero_bin_fraction(), None);
+
+        for i in 1..=90 {
+            dd.add(i as f64 * 1.0e-5);
+        }
+        for i in 1..=10 {
+            dd.add(i as f64);
+        }
+        assert_eq!(dd.zero_bin_fraction(), Some(0.9));
+
+        let mut dd = DDSketch::new(Config::defaults());
+        dd.add(1.0);
+        assert_eq!(dd.zero_bin_fraction(), Some(0.0));
+    }
+
+    #[test]
+    fn test_with_expected_range() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::with_expected_range(c, 1.0, 1000.0);
+        let mut reference = DDSketch::new(c);
+
+        let length = dd.length();
+        assert_eq!(length, (c.key(1000.0) - c.key(1.0) + 1) as usize);
+
+        for i in (1..1001).rev() {
+            dd.add(i as f64);
+            reference.add(i as f64);
+        }
+        assert_eq!(dd.length(), length);
+        for q in &[0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            assert_eq!(dd.quantile(*q).unwrap(), reference.quantile(*q).unwrap());
+        }
+
+        dd.add(0.01);
+        dd.add(1.0e5);
+        assert!(dd.length() > length);
+        assert_eq!(dd.min(), Some(0.01));
+        assert_eq!(dd.quantile(0.0005).unwrap().unwrap(), 0.01);
+
+        let clamped = DDSketch::with_expected_range(c, -1.0e9, 1.0e9);
+        assert_eq!(clamped.length(), c.max_num_bins as usize);
+    }
+
+    #[test]
+    fn test_conditional_mean_above() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..951 {
+            dd.add(i as f64 / 100.0);
+        }
+        for i in 0..50 {
+            dd.add(100.0 + i as f64 * 4.0);
+        }
+
+        let p95 = dd.quantile(0.95).unwrap().unwrap();
+        let shortfall = dd.conditional_mean_above(p95).unwrap();
+        assert!(shortfall > p95);
+        // The tail is 100 to 296, averaging 198
+        assert!((shortfall - 198.0).abs() <= 198.0 * c.alpha() * 2.0);
+
+        assert_eq!(dd.conditional_mean_above(1000.0), None);
+        assert_eq!(DDSketch::new(c).conditional_mean_above(0.0), None);
+    }
+
+    #[test]
+    fn test_trimmed_count() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.trimmed_count(0.1, 0.9).unwrap(), None);
+
+        for i in 0..1000 {
+            dd.add(i as f64);
+        }
+
+        assert_eq!(dd.trimmed_count(0.1, 0.9).unwrap(), Some(800));
+        assert_eq!(dd.trimmed_count(0.0, 1.0).unwrap(), Some(1000));
+        assert_eq!(dd.trimmed_count(0.5, 0.5).unwrap(), Some(0));
+
+        // Of ten samples the quartiles sit at ranks 3.25 and 7.75, leaving ranks 4 to 7, where
+        // scaling the count by the quantile range would give 5
+        let mut ten = DDSketch::new(c);
+        for i in 1..11 {
+            ten.add(i as f64);
+        }
+        assert_eq!(ten.trimmed_count(0.25, 0.75).unwrap(), Some(4));
+        assert_eq!(ten.trimmed_count(0.0, 0.5).unwrap(), Some(5));
+        assert_eq!(ten.trimmed_count(0.0, 1.0 / 9.0).unwrap(), Some(2));
+
+        // Samples sharing a bin are split at the boundary rank
+        let mut shared = DDSketch::new(c);
+        shared.add_n(1.0, 3);
+        shared.add_n(2.0, 3);
+        assert_eq!(shared.trimmed_count(0.5, 1.0).unwrap(), Some(3));
+        assert_eq!(shared.trimmed_count(0.3, 0.7).unwrap(), Some(2));
+        assert!(dd.trimmed_count(0.9, 0.1).is_err());
+        assert!(dd.trimmed_count(-0.1, 0.9).is_err());
+    }
+
+    #[test]
+    fn test_approx_samples() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in -100..1000 {
+            dd.add(i as f64 * 0.5);
+        }
+
+        let coarse = Config::new(0.05, 2048, 1.0e-9);
+        let mut resketched = DDSketch::new(coarse);
+        for v in dd.approx_samples() {
+            resketched.add(v);
+        }
+
+        assert_eq!(resketched.count(), dd.count());
+        assert_eq!(dd.approx_samples().count(), dd.count());
+        let tolerance = c.alpha() + coarse.alpha() + c.alpha() * coarse.alpha();
+        for q in &[0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            let expected = dd.quantile(*q).unwrap().unwrap();
+            let actual = resketched.quantile(*q).unwrap().unwrap();
+            assert!(
+                (actual - expected).abs() <= expected.abs() * tolerance,
+                "q = {}: {} vs {}",
+                q,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_with_extremes() {
+        let c = Config::defaults();
+        let mut d1 = DDSketch::new(c);
+        let mut d2 = DDSketch::new(c);
+
+        for i in 1..101 {
+            d1.add(i as f64);
+            d2.add(i as f64 * 2.0);
+        }
+
+        let mut merged = d1.clone();
+        merged
+            .merge_with_extremes(&d2, Some(0.5), Some(250.0))
+            .unwrap();
+        assert_eq!(merged.count(), 200);
+        assert_eq!(merged.sum(), Some(5050.0 * 3.0));
+        assert_eq!(merged.min(), Some(0.5));
+        assert_eq!(merged.max(), Some(250.0));
+        assert_eq!(merged.quantile(1.0).unwrap(), Some(250.0));
+
+        let mut merged = d1.clone();
+        merged.merge_with_extremes(&d2, None, Some(300.0)).unwrap();
+        assert_eq!(merged.min(), Some(1.0));
+        assert_eq!(merged.max(), Some(300.0));
+
+        let mut merged = d1.clone();
+        assert!(matches!(
+            merged.merge_with_extremes(&d2, Some(500.0), None),
+            Err(DDSketchError::Extremes)
+        ));
+        assert_eq!(merged.count(), 100);
+        assert!(merged
+            .merge_with_extremes(&d2, Some(f64::NAN), None)
+            .is_err());
+
+        let mut empty = DDSketch::new(c);
+        empty
+            .merge_with_extremes(&DDSketch::new(c), Some(1.0), Some(2.0))
+            .unwrap();
+        assert_eq!(empty.min(), None);
+    }
+
+    #[test]
+    fn test_merge_rebin() {
+        let fine = Config::new(0.01, 2048, 1.0e-9);
+        let coarse = Config::new(0.02, 2048, 1.0e-9);
+        let mut d1 = DDSketch::new(fine);
+        let mut d2 = DDSketch::new(coarse);
+        let mut exact = Vec::new();
+
+        for i in 1..1001 {
+            let (a, b) = (i as f64 * 0.37, i as f64 * 5.0);
+            d1.add(a);
+            d2.add(b);
+            exact.push(a);
+            exact.push(b);
+        }
+        exact.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        assert!(d2.merge(&d1).is_err());
+        d2.merge_rebin(&d1);
+
+        assert_eq!(d2.count(), 2000);
+        assert_eq!(d2.min(), Some(0.37));
+        assert_eq!(d2.max(), Some(5000.0));
+        let sum: f64 = exact.iter().sum();
+        assert!((d2.sum().unwrap() - sum).abs() <= sum * 1.0e-12);
+
+        let bound = 2.0 * fine.alpha() + coarse.alpha() + 2.0 * fine.alpha() * coarse.alpha();
+        for q in &[0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            let expected = exact[(q * 1999.0) as usize];
+            let actual = d2.quantile(*q).unwrap().unwrap();
+            assert!(
+                (actual - expected).abs() <= expected * bound,
+                "q = {}: {} vs {}",
+                q,
+                actual,
+                expected
+            );
+        }
+
+        // Identical configs merge exactly
+        let mut d3 = d1.clone();
+        d3.merge_rebin(&d1);
+        let mut d4 = d1.clone();
+        d4.merge(&d1).unwrap();
+        assert_eq!(d3.quantile(0.5).unwrap(), d4.quantile(0.5).unwrap());
+    }
+
+    #[test]
+    fn test_merge_and_report() {
+        let c = Config::new(0.01, 128, 1.0e-9);
+        let mut acc = DDSketch::new(c);
+        let mut small = DDSketch::new(c);
+
+        for i in 1..11 {
+            small.add(i as f64);
+        }
+
+        let report = acc.merge_and_report(&small).unwrap();
+        assert!(!report.collapsed);
+        assert_eq!(report.worst_case_rel_error, c.alpha());
+
+        let mut large = DDSketch::new(c);
+        for i in 1..101 {
+            large.add(i as f64 * 1.0e3);
+        }
+
+        let report = acc.merge_and_report(&large).unwrap();
+        assert!(report.collapsed);
+        assert_eq!(report.bins_after, acc.length());
+        assert!(report.worst_case_rel_error > c.alpha());
+
+        // A saturated sketch keeps reporting the degraded accuracy
+        let report = acc.merge_and_report(&DDSketch::new(c)).unwrap();
+        assert!(!report.collapsed);
+        assert!(report.worst_case_rel_error > c.alpha());
+
+        assert!(acc
+            .merge_and_report(&DDSketch::new(Config::defaults()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_merge_relaxed() {
+        let small = Config::new(0.01, 1024, 1.0e-9);
+        let large = Config::new(0.01, 4096, 1.0e-9);
+        let mut d1 = DDSketch::new(small);
+        let mut d2 = DDSketch::new(large);
+
+        for i in 1..101 {
+            d1.add(i as f64);
+            d2.add(i as f64 * 1.0e6);
+        }
+
+        assert!(d1.merge(&d2).is_err());
+        assert!(!d1.config_changed());
+
+        d1.merge_relaxed(&d2).unwrap();
+        assert_eq!(d1.count(), 200);
+        assert_eq!(d1.effective_config().max_num_bins, 4096);
+        assert_eq!(d1.effective_config(), large);
+        assert!(d1.config_changed());
+
+        // Clearing keeps the adopted config as the sketch's own
+        let mut cleared = d1.clone();
+        cleared.clear();
+        assert_eq!(cleared.effective_config(), large);
+        assert!(!cleared.config_changed());
+
+        // The larger side keeps its config
+        let mut d3 = DDSketch::new(large);
+        d3.merge_relaxed(&DDSketch::new(small)).unwrap();
+        assert_eq!(d3.effective_config(), large);
+        assert!(!d3.config_changed());
+
+        let other = DDSketch::new(Config::new(0.02, 1024, 1.0e-9));
+        assert!(d1.merge_relaxed(&other).is_err());
+    }
+
+    #[test]
+    fn test_max_value() {
+        let c = Config::defaults().with_max_value(1.0e3);
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..101 {
+            dd.add(i as f64);
+        }
+        dd.add(1.0e3);
+        let length = dd.length();
+
+        dd.add(1.0e15);
+        dd.add(-1.0e15);
+
+        assert_eq!(dd.count(), 101);
+        assert_eq!(dd.out_of_range_count(), 2);
+        assert_eq!(dd.max(), Some(1.0e3));
+        assert_eq!(dd.min(), Some(1.0));
+        assert_eq!(dd.length(), length);
+
+        let stats = dd.add_all(vec![5.0, 1.0e9, f64::NAN]);
+        assert_eq!(stats.added, 1);
+        assert_eq!(stats.skipped, 2);
+
+        let mut total = DDSketch::new(c);
+        total.merge(&dd).unwrap();
+        assert_eq!(total.out_of_range_count(), 3);
+    }
+
+    #[test]
+    fn test_quantile_band() {
+        let c = Config::defaults();
+        let mut series = Vec::new();
+
+        for minute in 1..6 {
+            let mut dd = DDSketch::new(c);
+            for i in 1..101 {
+                dd.add(i as f64 * minute as f64);
+            }
+            series.push(dd);
+        }
+        series.push(DDSketch::new(c));
+        let refs: Vec<&DDSketch> = series.iter().collect();
+
+        let (lo, median, hi) = quantile_band(&refs, 0.9).unwrap().unwrap();
+        for sketch in &series[..5] {
+            let v = sketch.quantile(0.9).unwrap().unwrap();
+            assert!(lo <= v && v <= hi);
+        }
+        assert_eq!(lo, series[0].quantile(0.9).unwrap().unwrap());
+        assert_eq!(median, series[2].quantile(0.9).unwrap().unwrap());
+        assert_eq!(hi, series[4].quantile(0.9).unwrap().unwrap());
+
+        let (lo, median, hi) = quantile_band(&refs[3..5], 1.0).unwrap().unwrap();
+        assert_eq!((lo, median, hi), (400.0, 450.0, 500.0));
+
+        assert_eq!(quantile_band(&refs[5..], 0.5).unwrap(), None);
+        assert_eq!(quantile_band(&[], 0.5).unwrap(), None);
+        assert!(quantile_band(&refs, 1.5).is_err());
+        assert!(quantile_band(&[], 1.5).is_err());
+
+        let other = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+        assert!(matches!(
+            quantile_band(&[&series[0], &other], 0.5),
+            Err(DDSketchError::Merge)
+        ));
+    }
+
+    #[test]
+    fn test_cdf_many() {
+        use rand::Rng;
+
+        let c = Config::new(0.01, 8192, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.cdf_many(&[1.0, 2.0]), vec![None, None]);
+        assert!(dd.cdf_many(&[]).is_empty());
+
+        for i in 1..=1000 {
+            dd.add(i as f64);
+            dd.add(-(i as f64) / 4.0);
+        }
+        dd.add(0.0);
+
+        let mut rng = rand::thread_rng();
+        let mut thresholds: Vec<f64> = (0..200).map(|_| rng.gen_range(-400.0, 1200.0)).collect();
+        thresholds.extend(&[0.0, -250.0, 1000.0, f64::NAN, f64::INFINITY]);
+
+        let batched = dd.cdf_many(&thresholds);
+        assert_eq!(batched.len(), thresholds.len());
+        for (v, p) in thresholds.iter().zip(&batched) {
+            assert_eq!(*p, dd.cdf(*v), "threshold {}", v);
+        }
+    }
+
+    #[test]
+    fn test_count_between() {
+        let c = Config::new(0.01, 8192, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.count_between(1.0, 2.0), None);
+
+        for i in 1..=1000 {
+            dd.add(i as f64);
+        }
+
+        let n = dd.count_between(250.0, 750.0).unwrap();
+        assert!((n as i64 - 501).abs() <= 15, "count {}", n);
+        assert_eq!(dd.count_between(1.0, 1000.0), Some(1000));
+        assert_eq!(dd.count_between(-100.0, 0.5), Some(0));
+        assert_eq!(dd.count_between(1001.0, 1.0e6), Some(0));
+        assert_eq!(dd.count_between(750.0, 250.0), None);
+        assert_eq!(dd.count_between(f64::NAN, 250.0), None);
+
+        for i in 1..=100 {
+            dd.add(-(i as f64));
+        }
+        dd.add(0.0);
+
+        // Spanning zero takes in the negative values, the zero bin and the positive values
+        let n = dd.count_between(-50.0, 50.0).unwrap();
+        assert!((n as i64 - 101).abs() <= 3, "count {}", n);
+        assert_eq!(dd.count_between(-1.0e6, 1.0e6), Some(1101));
+    }
+
+    #[test]
+    fn test_quantile_conservative() {
+        let c = Config::new(0.01, 8192, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.quantile_conservative(0.5).unwrap(), None);
+        assert!(dd.quantile_conservative(1.5).is_err());
+
+        for i in 1..=1000 {
+            dd.add(i as f64);
+            dd.add(-(i as f64) / 10.0);
+        }
+
+        for i in 0..=100 {
+            let q = i as f64 / 100.0;
+            let conservative = dd.quantile_conservative(q).unwrap().unwrap();
+            assert!(
+                conservative >= dd.quantile(q).unwrap().unwrap(),
+                "q = {}",
+                q
+            );
+            assert!(conservative <= dd.max().unwrap());
+        }
+
+        // The upper bound of the bin is at most one bin width away
+        let q = dd.quantile(0.9).unwrap().unwrap();
+        let conservative = dd.quantile_conservative(0.9).unwrap().unwrap();
+        assert!(conservative > q && conservative <= q * c.gamma);
+    }
+
+    #[test]
+    fn test_quantile_optimistic() {
+        let c = Config::new(0.01, 8192, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.quantile_optimistic(0.5).unwrap(), None);
+        assert!(dd.quantile_optimistic(-0.5).is_err());
+
+        for i in 1..=1000 {
+            dd.add(i as f64);
+            dd.add(-(i as f64) / 10.0);
+        }
+
+        for i in 0..=100 {
+            let q = i as f64 / 100.0;
+            let optimistic = dd.quantile_optimistic(q).unwrap().unwrap();
+            let value = dd.quantile(q).unwrap().unwrap();
+            let conservative = dd.quantile_conservative(q).unwrap().unwrap();
+            assert!(optimistic <= value && value <= conservative, "q = {}", q);
+            assert!(optimistic >= dd.min().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_quantile_sample_adequacy() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.quantile_sample_adequacy(0.5).unwrap(), None);
+        assert!(dd.quantile_sample_adequacy(2.0).is_err());
+
+        // A dense body with a handful of widely spread outliers in the tail
+        for i in 0..10_000 {
+            dd.add(10.0 + (i % 10) as f64 * 0.01);
+        }
+        for i in 0..5 {
+            dd.add(1000.0 * 2.0_f64.powi(i));
+        }
+
+        let body = dd.quantile_sample_adequacy(0.5).unwrap().unwrap();
+        assert!(body.adequate);
+        assert!(body.support >= 1000);
+
+        let tail = dd.quantile_sample_adequacy(0.9999).unwrap().unwrap();
+        assert_eq!(tail.support, 1);
+        assert!(!tail.adequate);
+    }
+
+    #[test]
+    fn test_cdf() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.cdf(1.0), None);
+
+        for i in 1..=100 {
+            dd.add(i as f64);
+        }
+
+        assert!((dd.cdf(50.0).unwrap() - 0.5).abs() <= 0.01);
+        assert_eq!(dd.cdf(0.5), Some(0.0));
+        assert_eq!(dd.cdf(-10.0), Some(0.0));
+        assert_eq!(dd.cdf(100.0), Some(1.0));
+        assert_eq!(dd.cdf(1.0e6), Some(1.0));
+
+        // The bin of a quantile's value holds the rank it was looked up by
+        for q in &[0.05, 0.1, 0.25, 0.5, 0.75, 0.9, 0.95] {
+            let v = dd.quantile(*q).unwrap().unwrap();
+            let p = dd.cdf(v).unwrap();
+            assert!(p >= *q && p - q <= 0.02, "q = {}: cdf {}", q, p);
+        }
+
+        let mut last = 0.0;
+        for i in 0..200 {
+            let p = dd.cdf(i as f64 * 0.6).unwrap();
+            assert!(p >= last);
+            last = p;
+        }
+    }
+
+    #[test]
+    fn test_smart_merge() {
+        let c = Config::new(0.01, 1024, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+        dd.extend((1..=100).map(|i| i as f64));
+
+        let mut wider = DDSketch::new(Config::new(0.01, 2048, 1.0e-9));
+        wider.add_all(vec![500.0, 1000.0]);
+        assert_eq!(dd.smart_merge(&wider).unwrap(), MergeKind::Exact);
+        assert_eq!(dd.count(), 102);
+        assert_eq!(dd.config.max_num_bins, 2048);
+
+        let mut coarse = DDSketch::new(Config::new(0.05, 2048, 1.0e-9));
+        coarse.add_all(vec![0.5, 2000.0]);
+        assert_eq!(dd.smart_merge(&coarse).unwrap(), MergeKind::Folded);
+        assert_eq!(dd.count(), 104);
+        assert_eq!(dd.max(), Some(2000.0));
+        assert_eq!(dd.config, Config::new(0.01, 2048, 1.0e-9));
+
+        let mut far = DDSketch::new(Config::new(0.01, 2048, 1.0e-3));
+        far.add(1.0);
+        assert!(dd.smart_merge(&far).is_err());
+        let mut log = DDSketch::new(Config::log_centered(0.01, 2048));
+        log.add(1.0);
+        assert!(dd.smart_merge(&log).is_err());
+        assert_eq!(dd.count(), 104);
+    }
+
+    #[test]
+    fn test_quantile_with_bounds() {
+        let fine = Config::new(0.01, 2048, 1.0e-9);
+        let coarse = Config::new(0.05, 2048, 1.0e-9);
+        let mut widths = Vec::new();
+        for c in &[fine, coarse] {
+            let mut dd = DDSketch::new(*c);
+            assert_eq!(dd.quantile_with_bounds(0.5).unwrap(), None);
+            for i in -100..=1000 {
+                dd.add(i as f64);
+            }
+
+            for q in &[0.0, 0.01, 0.05, 0.5, 0.9, 1.0] {
+                let (estimate, lower, upper) = dd.quantile_with_bounds(*q).unwrap().unwrap();
+                assert_eq!(Some(estimate), dd.quantile(*q).unwrap());
+                assert!(lower <= estimate && estimate <= upper, "q={}", q);
+                assert!(lower >= -100.0 && upper <= 1000.0);
+            }
+            let (estimate, lower, upper) = dd.quantile_with_bounds(0.5).unwrap().unwrap();
+            assert!((upper - lower - 2.0 * estimate * c.alpha()).abs() < 1.0e-9);
+            widths.push(upper - lower);
+
+            // Mirrored for negative values
+            let (estimate, lower, upper) = dd.quantile_with_bounds(0.05).unwrap().unwrap();
+            assert!(estimate < 0.0 && lower < estimate && upper > estimate);
+        }
+        assert!(widths[1] > widths[0] * 4.0);
+
+        assert!(DDSketch::new(fine).quantile_with_bounds(1.5).is_err());
+    }
+
+    #[test]
+    fn test_add_operators() {
+        let c = Config::defaults();
+        let mut d1 = DDSketch::new(c);
+        let mut d2 = DDSketch::new(c);
+        for i in 1..=100 {
+            d1.add(i as f64);
+            d2.add(i as f64 * 7.5);
+        }
+
+        let mut expected = d1.clone();
+        expected.merge(&d2).unwrap();
+
+        let sum = (&d1 + &d2).unwrap();
+        assert_eq!(sum, expected);
+        assert_eq!(d1.count(), 100);
+        assert_eq!(d2.count(), 100);
+
+        let mut acc = d1.clone();
+        acc += &d2;
+        assert_eq!(acc, expected);
+
+        let other = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+        assert!((&d1 + &other).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "can not merge sketches with different configs")]
+    fn test_add_assign_mismatch() {
+        let mut dd = DDSketch::new(Config::defaults());
+        dd += &DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+    }
+
+    #[test]
+    fn test_report() {
+        let mut dd = DDSketch::new(Config::defaults());
+        let empty = dd.report();
+        assert_eq!(empty.count, 0);
+        assert_eq!(empty.min, None);
+        assert_eq!(empty.p99, None);
+
+        for i in 1..=1000 {
+            dd.add(i as f64);
+        }
+        let report = dd.report();
+        assert_eq!(report.count, dd.count() as u64);
+        assert_eq!(report.min, dd.min());
+        assert_eq!(report.max, dd.max());
+        assert_eq!(report.sum, dd.sum());
+        assert_eq!(report.mean, dd.mean());
+        assert_eq!(report.p50, dd.quantile(0.5).unwrap());
+        assert_eq!(report.p90, dd.quantile(0.9).unwrap());
+        assert_eq!(report.p99, dd.quantile(0.99).unwrap());
+    }
+
+    #[test]
+    fn test_debug() {
+        let mut dd = DDSketch::new(Config::defaults());
+        assert!(format!("{:?}", dd).starts_with(
+            "DDSketch { count: 0, min: None, max: None, sum: None, bins: 0, alpha: 0.0"
+        ));
+
+        for i in 1..=1000 {
+            dd.add(i as f64);
+        }
+        let debug = format!("{:?}", dd);
+        assert!(debug.contains("count: 1000"), "{}", debug);
+        assert!(debug.contains("min: Some(1.0)"), "{}", debug);
+        assert!(debug.contains("max: Some(1000.0)"), "{}", debug);
+        assert!(!debug.contains('['), "{}", debug);
+        assert!(debug.len() < 200, "{}", debug);
+    }
+
+    #[test]
+    fn test_trimmed_mean() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+        assert_eq!(dd.trimmed_mean(0.1, 0.9).unwrap(), None);
+
+        for i in 1..=100 {
+            dd.add(i as f64);
+        }
+        // The samples ranked 11 to 90, each counted at its bin's lower bound
+        let mean = dd.trimmed_mean(0.1, 0.9).unwrap().unwrap();
+        assert!(
+            mean <= 50.5 && mean >= 50.5 * (1.0 - 2.0 * c.alpha()),
+            "mean {}",
+            mean
+        );
+        assert_eq!(dd.trimmed_count(0.1, 0.9).unwrap(), Some(80));
+
+        // The outliers land in the trimmed tails
+        dd.add_all(vec![1.0e9, 2.0e9, 1.0e-6]);
+        let robust = dd.trimmed_mean(0.1, 0.9).unwrap().unwrap();
+        assert!(
+            (robust - 50.5).abs() <= 50.5 * 2.0 * c.alpha(),
+            "mean {}",
+            robust
+        );
+        assert!(dd.mean().unwrap() > 1.0e7);
+
+        assert!(dd.trimmed_mean(0.9, 0.1).is_err());
+        assert!(dd.trimmed_mean(0.5, 0.5).is_err());
+        assert!(dd.trimmed_mean(-0.1, 0.9).is_err());
+
+        // Between the two samples' ranks of 1 and 2, so none is kept
+        let two: DDSketch = vec![1.0, 2.0].into_iter().collect();
+        assert_eq!(two.trimmed_count(0.1, 0.4).unwrap(), Some(0));
+        assert_eq!(two.trimmed_mean(0.1, 0.4).unwrap(), None);
+    }
+
+    #[test]
+    fn test_abs() {
+        // Wide enough that both signs fit without collapsing
+        let c = Config::new(0.01, 8192, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+        let mut positive = DDSketch::new(c);
+        for i in 1..=100 {
+            dd.add(i as f64);
+            dd.add(-(i as f64));
+            positive.add(i as f64);
+        }
+
+        let folded = dd.abs();
+        assert_eq!(folded.count(), 200);
+        assert_eq!(folded.max(), Some(100.0));
+        assert!((folded.min().unwrap() - 1.0).abs() <= c.alpha());
+        assert!((folded.sum().unwrap() - 10100.0).abs() <= 10100.0 * c.alpha());
+        for q in &[0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            let a = folded.quantile(*q).unwrap().unwrap();
+            let b = positive.quantile(*q).unwrap().unwrap();
+            assert!(
+                (a - b).abs() <= b * 2.0 * c.alpha(),
+                "q={}: {} vs {}",
+                q,
+                a,
+                b
+            );
+        }
+
+        // Only negative values fold exactly
+        let mut negative = DDSketch::new(c);
+        negative.add_all(vec![-1.0, -5.0, -20.0]);
+        let folded = negative.abs();
+        assert_eq!(folded.min(), Some(1.0));
+        assert_eq!(folded.max(), Some(20.0));
+        assert_eq!(folded.sum(), Some(26.0));
+        assert!(folded.extremes_trusted());
+        assert_eq!(positive.abs(), positive);
+    }
+
+    #[test]
+    fn test_default() {
+        let mut dd = DDSketch::default();
+        let mut expected = DDSketch::new(Config::defaults());
+        assert_eq!(Config::default(), Config::defaults());
+        assert_eq!(dd.config.provenance(), Provenance::Default);
+
+        for i in 1..=100 {
+            dd.add(i as f64);
+            expected.add(i as f64);
+        }
+        for q in &[0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            assert_eq!(dd.quantile(*q).unwrap(), expected.quantile(*q).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_add_f32() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+        let mut expected = DDSketch::new(c);
+
+        // The f32 closest to 1.0e-9 is just below it, and the next one up is just above
+        let below = 1.0e-9_f32;
+        let above = f32::from_bits(below.to_bits() + 1);
+        assert!(f64::from(below) < c.min_value() && f64::from(above) > c.min_value());
+
+        for v in &[below, above, 1.5e-9, 0.1, 1.0, 3.3e5] {
+            dd.add_f32(*v);
+            expected.add(f64::from(*v));
+        }
+        assert_eq!(c.key(f64::from(below)), 0);
+        assert_eq!(c.key(f64::from(above)), c.key(1.0e-9 * (1.0 + 1.0e-7)));
+        dd.add_n_f32(above, 3);
+        expected.add_n(f64::from(above), 3);
+
+        assert_eq!(dd.zero_count(), 1);
+        assert_eq!(dd, expected);
+        for q in &[0.0, 0.1, 0.5, 0.9, 1.0] {
+            assert_eq!(dd.quantile(*q).unwrap(), expected.quantile(*q).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_add_checked() {
+        let c = Config::new(0.01, 128, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.add_checked(1.0), AddOutcome::Exact);
+        assert_eq!(dd.add_checked(1.5), AddOutcome::Exact);
+        // Far below the 128 bins the store can hold above 1.0
+        assert_eq!(dd.add_checked(1.0e-6), AddOutcome::CollapsedLow);
+        assert_eq!(dd.add_checked(f64::NAN), AddOutcome::Rejected);
+        assert_eq!(dd.collapse_events(), 1);
+        assert_eq!(dd.count(), 3);
+
+        let mut dd = DDSketch::new(c.with_collapse_strategy(CollapseStrategy::CollapseHighest));
+        assert_eq!(dd.add_checked(1.0), AddOutcome::Exact);
+        assert_eq!(dd.add_checked(1.0e6), AddOutcome::CollapsedHigh);
+        assert_eq!(dd.add_checked(-1.0e6), AddOutcome::Exact);
+        // The window followed the low sample, so everything above it now collapses
+        assert_eq!(dd.add_checked(2.0), AddOutcome::CollapsedHigh);
+    }
+
+    #[test]
+    fn test_sum_fraction_above_quantile() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+        assert_eq!(dd.sum_fraction_above_quantile(0.8).unwrap(), None);
+
+        // Pareto distributed, so the tail carries most of the total
+        for i in 1..=1000 {
+            let u = i as f64 / 1001.0;
+            dd.add((1.0 - u).powf(-1.0 / 1.16));
+        }
+
+        let top = dd.sum_fraction_above_quantile(0.8).unwrap().unwrap();
+        assert!(top > 0.6 && top <= 1.0, "top={}", top);
+        assert!(dd.sum_fraction_above_quantile(0.5).unwrap().unwrap() > top);
+        assert_eq!(dd.sum_fraction_above_quantile(1.0).unwrap(), Some(0.0));
+        assert!(dd.sum_fraction_above_quantile(1.1).is_err());
+    }
+
+    #[test]
+    fn test_gini() {
+        let c = Config::defaults();
+        let mut equal = DDSketch::new(c);
+
+        assert_eq!(equal.gini(), None);
+
+        for _ in 0..1000 {
+            equal.add(42.0);
+        }
+        assert!(equal.gini().unwrap().abs() < 1.0e-12);
+
+        // Uniform on [0, 1000] has a Gini coefficient of 1/3
+        let mut uniform = DDSketch::new(c);
+        for i in 0..1001 {
+            uniform.add(i as f64);
+        }
+        assert!((uniform.gini().unwrap() - 1.0 / 3.0).abs() < 0.01);
+
+        // A few samples holding most of the total
+        let mut skewed = DDSketch::new(c);
+        for _ in 0..990 {
+            skewed.add(1.0);
+        }
+        for _ in 0..10 {
+            skewed.add(10000.0);
+        }
+        let gini = skewed.gini().unwrap();
+        assert!(gini > 0.9 && gini < 1.0, "gini {}", gini);
+
+        let mut negative = DDSketch::new(c);
+        negative.add(-1.0);
+        negative.add(5.0);
+        assert_eq!(negative.gini(), None);
+
+        let mut zeros = DDSketch::new(c);
+        zeros.add(0.0);
+        assert_eq!(zeros.gini(), None);
+    }
+
+    #[test]
+    fn test_quantile_unscaled() {
+        let c = Config::defaults();
+        let mut original = DDSketch::new(c);
+
+        for i in 1..1001 {
+            original.add(i as f64);
+        }
+
+        let mut scaled = original.clone();
+        scaled.scale(1000.0);
+        assert_eq!(scaled.scale_factor(), 1000.0);
+
+        let mut mirrored = original.clone();
+        mirrored.scale(-0.5);
+
+        for q in &[0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            let expected = original.quantile(*q).unwrap().unwrap();
+            for sketch in &[&original, &scaled, &mirrored] {
+                let v = sketch.quantile_unscaled(*q).unwrap().unwrap();
+                assert!(
+                    (v - expected).abs() <= expected * c.alpha() * 2.0,
+                    "q = {}: {} vs {}",
+                    q,
+                    v,
+                    expected
+                );
+            }
+        }
+
+        let mut zeroed = original.clone();
+        zeroed.scale(0.0);
+        assert_eq!(zeroed.quantile_unscaled(0.5).unwrap(), None);
+        assert!(scaled.quantile_unscaled(1.5).is_err());
+        assert!(mirrored.quantile_unscaled(-0.5).is_err());
+
+        scaled.clear();
+        assert_eq!(scaled.scale_factor(), 1.0);
+    }
+
+    #[test]
+    fn test_merge_empty() {
+        let c = Config::defaults();
+        let mut d1 = DDSketch::new(c);
+        let d2 = DDSketch::new(c);
+
+        d1.merge(&d2).unwrap();
+
+        assert!(d1.is_empty());
+        assert_eq!(d1.min(), None);
+        assert_eq!(d1.max(), None);
+        assert_eq!(d1.sum(), None);
+        assert_eq!(d1.quantile(0.5).unwrap(), None);
+
+        for i in 1..101 {
+            d1.add(i as f64);
+        }
+
+        assert!(!d1.is_empty());
+        assert_eq!(d1.min(), Some(1.0));
+        assert_eq!(d1.max(), Some(100.0));
+        assert_eq!(d1.sum(), Some(5050.0));
+        assert_eq!(d1.quantile(0.0).unwrap(), Some(1.0));
+        assert_eq!(d1.quantile(1.0).unwrap(), Some(100.0));
+        assert_eq!(d1.quantile(0.5).unwrap().unwrap().ceil(), 50.0);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..1001 {
+            dd.add(i as f64);
+        }
+
+        let snapshot = dd.snapshot();
+        let frozen: Vec<_> = [0.1, 0.5, 0.99]
+            .iter()
+            .map(|q| snapshot.quantile(*q).unwrap())
+            .collect();
+
+        for i in 1..10001 {
+            dd.add(i as f64 * 100.0);
+        }
+
+        assert_eq!(snapshot.count(), 1000);
+        assert_eq!(snapshot.max(), Some(1000.0));
+        for (q, expected) in [0.1, 0.5, 0.99].iter().zip(frozen) {
+            assert_eq!(snapshot.quantile(*q).unwrap(), expected);
+        }
+        assert_eq!(dd.count(), 11000);
+    }
+
+    #[test]
+    fn test_quantile_full() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.quantile_full(0.5).unwrap(), None);
+
+        for _ in 0..10 {
+            dd.add(100.0);
+        }
+        for _ in 0..5 {
+            dd.add(1000.0);
+        }
+
+        let median = dd.quantile_full(0.5).unwrap().unwrap();
+        assert_eq!(median.q, 0.5);
+        assert_eq!(Some(median.value), dd.quantile(0.5).unwrap());
+        assert_eq!(median.support, 10);
+        // Every sample in the bin is exactly 100, so a representative below that is clamped
+        assert_eq!(median.clamped, c.value(c.key(100.0)) < 100.0);
+
+        let top = dd.quantile_full(1.0).unwrap().unwrap();
+        assert_eq!(top.value, 1000.0);
+        assert_eq!(top.support, 5);
+        assert!(!top.clamped);
+
+        let p90 = dd.quantile_full(0.9).unwrap().unwrap();
+        assert_eq!(p90.support, 5);
+        assert!(p90.clamped == (c.value(c.key(1000.0)) > 1000.0));
+        assert_eq!(top.to_string(), format!("q1=1000 (support 5)"));
+
+        assert!(dd.quantile_full(-0.5).is_err());
+    }
+
+    #[test]
+    fn test_clear() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..1001 {
+            dd.add(i as f64);
+        }
+        let length = dd.length();
+
+        dd.clear();
+        assert!(dd.is_empty());
+        assert_eq!(dd.quantile(0.5).unwrap(), None);
+        assert_eq!(dd.length(), length);
+        assert_eq!(dd.config, c);
+
+        let mut fresh = DDSketch::new(c);
+        for i in 1..101 {
+            dd.add(i as f64 * 3.0);
+            fresh.add(i as f64 * 3.0);
+        }
+        assert_eq!(dd.length(), length);
+        assert_eq!(dd.count(), 100);
+        assert_eq!(dd.sum(), fresh.sum());
+        for q in &[0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            assert_eq!(dd.quantile(*q).unwrap(), fresh.quantile(*q).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_clear_then_merge() {
+        let c = Config::defaults();
+        let mut populated = DDSketch::new(c);
+        let mut cleared = DDSketch::new(c);
+
+        for i in 1..101 {
+            populated.add(i as f64);
+            cleared.add(-(i as f64) * 1000.0);
+        }
+        cleared.add(f64::NAN);
+        cleared.clear();
+
+        assert_eq!(cleared.count(), 0);
+        assert_eq!(cleared.min(), None);
+        assert_eq!(cleared.max(), None);
+        assert_eq!(cleared.sum(), None);
+        assert_eq!(cleared.nan_count(), 0);
+
+        // Cleared into populated leaves the extremes alone
+        let mut into_populated = populated.clone();
+        into_populated.merge(&cleared).unwrap();
+        assert_eq!(into_populated.min(), Some(1.0));
+        assert_eq!(into_populated.max(), Some(100.0));
+        assert_eq!(into_populated.sum(), populated.sum());
+        assert_eq!(into_populated.count(), 100);
+
+        // Populated into cleared takes the extremes of the populated sketch
+        cleared.merge(&populated).unwrap();
+        assert_eq!(cleared.min(), Some(1.0));
+        assert_eq!(cleared.max(), Some(100.0));
+        assert_eq!(cleared.sum(), populated.sum());
+        assert_eq!(cleared.count(), 100);
+        for q in &[0.0, 0.1, 0.5, 0.9, 1.0] {
+            assert_eq!(
+                cleared.quantile(*q).unwrap(),
+                populated.quantile(*q).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_quantiles() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+        let qs = [0.99, 0.5, 0.0, 0.9, 1.0, 0.5, 0.01, 0.95];
+
+        assert_eq!(dd.quantiles(&qs).unwrap(), vec![None; 8]);
+
+        for i in -200..800 {
+            dd.add(i as f64 * 1.1);
+        }
+        for _ in 0..50 {
+            dd.add(0.0);
+        }
+
+        let expected: Vec<_> = qs.iter().map(|q| dd.quantile(*q).unwrap()).collect();
+        assert_eq!(dd.quantiles(&qs).unwrap(), expected);
+        assert_eq!(dd.quantiles(&[]).unwrap(), vec![]);
+        assert!(dd.quantiles(&[0.5, 1.01]).is_err());
+    }
+
+    #[test]
+    fn test_quantiles_with_scratch() {
+        let c = Config::defaults();
+        let qs = [0.0, 0.01, 0.1, 0.5, 0.9, 0.99, 0.999, 1.0];
+        let mut scratch = Vec::new();
+        let mut out = [None; 8];
+
+        let mut dd = DDSketch::new(c);
+        dd.quantiles_with_scratch(&qs, &mut scratch, &mut out)
+            .unwrap();
+        assert_eq!(out, [None; 8]);
+
+        for n in &[1000, 10, 5000] {
+            let mut dd = DDSketch::new(c);
+            for i in 0..*n {
+                dd.add((i as f64 - 100.0) * 1.5);
+            }
+
+            dd.quantiles_with_scratch(&qs, &mut scratch, &mut out)
+                .unwrap();
+            let mut fresh = [None; 8];
+            dd.quantiles_with_scratch(&qs, &mut Vec::new(), &mut fresh)
+                .unwrap();
+
+            assert_eq!(out, fresh);
+            for (q, v) in qs.iter().zip(out.iter()) {
+                assert_eq!(*v, dd.quantile(*q).unwrap());
+            }
+        }
+
+        dd.add(1.0);
+        assert!(dd
+            .quantiles_with_scratch(&[0.5, 1.5], &mut scratch, &mut out)
+            .is_err());
+    }
+
+    #[test]
+    fn test_health_report() {
+        let c = Config::new(0.01, 512, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..101 {
+            dd.add(i as f64);
+        }
+
+        let report = dd.health_report();
+        assert_eq!(report.relative_accuracy, c.alpha());
+        assert_eq!(report.occupied_bins, dd.occupied_bins());
+        assert_eq!(report.allocated_bins, dd.length());
+        assert_eq!(report.total_count, dd.count() as u64);
+        assert!(!report.is_saturated);
+
+        dd.add(1.0e9);
+        let report = dd.health_report();
+        assert_eq!(report.allocated_bins, 512);
+        assert!(report.is_saturated);
+
+        // The initial allocation alone exceeds a small bin limit without saturating it
+        let c = Config::new(0.01, 16, 1.0e-9);
+        let mut small = DDSketch::new(c);
+        small.add(1.0);
+        assert!(small.length() >= 16);
+        assert!(!small.health_report().is_saturated);
+        // Spanning exactly the limit leaves no room to grow
+        small.add(c.value(c.key(1.0) + 15));
+        assert!(small.health_report().is_saturated);
+
+        small.clear();
+        small.add(1.0);
+        small.add(1.0e9);
+        assert!(small.health_report().is_saturated);
+    }
+
+    #[test]
+    fn test_min_max_key() {
+        // Wide enough that mixing signs never collapses the lowest keys
+        let c = Config::new(0.01, 8192, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.min_key(), None);
+        assert_eq!(dd.max_key(), None);
+
+        for i in -20..200 {
+            dd.add(i as f64 * 0.75);
+        }
+
+        assert_eq!(dd.min_key(), Some(c.key(dd.min().unwrap())));
+        assert_eq!(dd.max_key(), Some(c.key(dd.max().unwrap())));
+        assert!(dd.min_key() < Some(0));
+    }
+
+    #[test]
+    fn test_single_bin_quantile() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 0..100 {
+            dd.add(42.0 + i as f64 * 1.0e-3);
+        }
+        assert_eq!(dd.occupied_bins(), 1);
+
+        let qs = [0.0, 0.01, 0.25, 0.5, 0.75, 0.99, 1.0];
+        let batch = dd.quantiles(&qs).unwrap();
+        for (q, batched) in qs.iter().zip(batch) {
+            let general = if *q == 0.0 {
+                dd.min
+            } else if *q == 1.0 {
+                dd.max
+            } else {
+                let key = dd.store.key_at_rank((q * 99.0 + 1.0) as u64);
+                c.value(key).max(dd.min).min(dd.max)
+            };
+            assert_eq!(dd.quantile(*q).unwrap(), Some(general));
+            assert_eq!(batched, Some(general));
+        }
+        for (q, v) in dd.tail_quantiles(2) {
+            assert_eq!(dd.quantile(q).unwrap(), Some(v));
+        }
+
+        dd.add(1000.0);
+        assert_eq!(dd.occupied_bins(), 2);
+        assert_eq!(dd.quantile(0.5).unwrap(), Some(dd.value_at_rank(51)));
+    }
+
+    #[test]
+    fn test_peak_count() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.peak_count(0.1), 0);
+
+        // Two smooth modes around 10 and 1000, from the logistic quantile function in log space
+        for i in 1..1000 {
+            let p = i as f64 / 1000.0;
+            let spread = (0.05 * (p / (1.0 - p)).ln()).exp();
+            dd.add(10.0 * spread);
+            dd.add(1000.0 * spread);
+        }
+
+        assert_eq!(dd.peak_count(0.2), 2);
+        assert!(dd.peak_count(0.0) > 2);
+
+        let mut unimodal = DDSketch::new(c);
+        for i in 1..1000 {
+            let p = i as f64 / 1000.0;
+            unimodal.add(50.0 * (0.05 * (p / (1.0 - p)).ln()).exp());
+        }
+        assert_eq!(unimodal.peak_count(0.2), 1);
+    }
+
+    #[test]
+    fn test_percentile_rank_of() {
+        let c = Config::defaults();
+        let mut baseline = DDSketch::new(c);
+
+        assert_eq!(baseline.percentile_rank_of(1.0), None);
+
+        for i in 1..1001 {
+            baseline.add(i as f64);
+        }
+
+        let median = baseline.quantile(0.5).unwrap().unwrap();
+        let rank = baseline.percentile_rank_of(median).unwrap();
+        assert!((rank - 50.0).abs() <= 1.0, "rank {}", rank);
+
+        assert_eq!(baseline.percentile_rank_of(0.0), Some(0.0));
+        assert_eq!(baseline.percentile_rank_of(1000.0), Some(100.0));
+    }
+
+    #[test]
+    fn test_exceeds_quantile() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.exceeds_quantile(1.0, 0.5).unwrap(), None);
+
+        for i in 1..1001 {
+            dd.add(i as f64);
+        }
+
+        for q in &[0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            let quantile = dd.quantile(*q).unwrap().unwrap();
+            for v in &[
+                -1.0, 1.0, 50.0, 99.5, 500.0, 899.0, 950.0, 990.0, 1000.0, 2000.0,
+            ] {
+                if c.key(*v) == c.key(quantile) && *q > 0.0 && *q < 1.0 {
+                    continue;
+                }
+                assert_eq!(
+                    dd.exceeds_quantile(*v, *q).unwrap(),
+                    Some(*v > quantile),
+                    "v = {}, q = {}",
+                    v,
+                    q
+                );
+            }
+        }
+
+        assert!(dd.exceeds_quantile(1.0, 1.5).is_err());
+    }
+
+    #[test]
+    fn test_from_iter_and_extend() {
+        let collected: DDSketch = (1..1001).map(|i| i as f64).collect();
+        let mut manual = DDSketch::new(Config::defaults());
+        for i in 1..1001 {
+            manual.add(i as f64);
+        }
+
+        assert_eq!(collected.config, Config::defaults());
+        assert_eq!(collected.count(), manual.count());
+        assert_eq!(collected.sum(), manual.sum());
+        for q in &[0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            assert_eq!(
+                collected.quantile(*q).unwrap(),
+                manual.quantile(*q).unwrap()
+            );
+        }
+
+        let c = Config::new(0.02, 1024, 1.0e-9);
+        let mut extended = DDSketch::new(c);
+        let mut manual = DDSketch::new(c);
+        extended.add(-5.0);
+        manual.add(-5.0);
+        extended.extend(vec![3.0, 1.0e4, f64::NAN, 0.5]);
+        for v in &[3.0, 1.0e4, f64::NAN, 0.5] {
+            manual.add(*v);
+        }
+
+        assert_eq!(extended.count(), 4);
+        assert_eq!(extended.nan_count(), 1);
+        assert_eq!(extended.min(), Some(-5.0));
+        assert_eq!(extended.max(), Some(1.0e4));
+        assert_eq!(extended.sum(), manual.sum());
+        for q in &[0.0, 0.25, 0.5, 0.75, 1.0] {
+            assert_eq!(extended.quantile(*q).unwrap(), manual.quantile(*q).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_weighted_mean() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        dd.add_n(7.0, 0);
+        assert_eq!(dd.mean(), None);
+        assert!(dd.is_empty());
+
+        dd.add(2.0);
+        dd.add_n(10.0, 3);
+        dd.add_n(-4.0, 2);
+        let key = c.key(100.0);
+        dd.add_key_n(key, 4);
+        dd.add_n(f64::NAN, 5);
+
+        let expected = (2.0 + 10.0 * 3.0 - 4.0 * 2.0 + c.value(key) * 4.0) / 10.0;
+        assert_eq!(dd.count(), 10);
+        assert_eq!(dd.nan_count(), 5);
+        assert!((dd.mean().unwrap() - expected).abs() <= 1.0e-9);
+        assert_eq!(dd.max(), Some(c.value(key)));
+        assert_eq!(dd.min(), Some(-4.0));
+
+        let mut repeated = DDSketch::new(c);
+        repeated.add(2.0);
+        for _ in 0..3 {
+            repeated.add(10.0);
+        }
+        for _ in 0..2 {
+            repeated.add(-4.0);
+        }
+        for _ in 0..4 {
+            repeated.add(c.value(key));
+        }
+        for q in &[0.0, 0.25, 0.5, 0.75, 1.0] {
+            assert_eq!(dd.quantile(*q).unwrap(), repeated.quantile(*q).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_bins() {
+        let c = Config::new(0.01, 8192, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.bins().count(), 0);
+
+        for i in 1..=500 {
+            dd.add(i as f64);
+            dd.add(-(i as f64) * 2.0);
+        }
+        dd.add(0.0);
+
+        let bins: Vec<_> = dd.bins().collect();
+        assert_eq!(bins.len(), dd.occupied_bins());
+        assert_eq!(bins.iter().map(|b| b.1).sum::<u64>(), dd.count() as u64);
+        assert!(bins.iter().all(|b| b.1 > 0));
+        assert!(bins.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(bins[0].0 <= -1000.0);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let c = Config::new(0.01, 8192, 1.0e-9);
+        let mut d1 = DDSketch::new(c);
+        let mut d2 = DDSketch::with_expected_range(c, -50.0, 5000.0);
+
+        assert_eq!(d1.fingerprint(), d2.fingerprint());
+
+        for i in 1..=100 {
+            d1.add(i as f64);
+            d2.add((101 - i) as f64);
+        }
+        assert_eq!(d1.fingerprint(), d2.fingerprint());
+
+        let before = d1.fingerprint();
+        d1.add(42.0);
+        assert_ne!(d1.fingerprint(), before);
+
+        // Rejected samples are not compared, as by `==`
+        let before = d2.fingerprint();
+        d2.add(f64::NAN);
+        assert_eq!(d2.fingerprint(), before);
+
+        let other = DDSketch::new(Config::new(0.02, 8192, 1.0e-9));
+        assert_ne!(other.fingerprint(), DDSketch::new(c).fingerprint());
+
+        let highest = DDSketch::new(c.with_collapse_strategy(CollapseStrategy::CollapseHighest));
+        assert_ne!(highest.fingerprint(), DDSketch::new(c).fingerprint());
+
+        // The bins match, and the fingerprints with them, even though the sums round differently
+        let forward: DDSketch = vec![0.1, 0.2, 0.3].into_iter().collect();
+        let reverse: DDSketch = vec![0.3, 0.2, 0.1].into_iter().collect();
+        assert_ne!(forward.sum(), reverse.sum());
+        assert_eq!(forward.fingerprint(), reverse.fingerprint());
+    }
+
+    #[test]
+    fn test_split_at() {
+        let c = Config::new(0.01, 8192, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..=1000 {
+            dd.add(i as f64);
+            dd.add(-(i as f64));
+        }
+        dd.add(f64::NAN);
+
+        let (below, above) = dd.split_at(250.0).unwrap();
+        assert_eq!(below.count() + above.count(), dd.count());
+        assert!((above.count() as i64 - 751).abs() <= 3);
+        assert_eq!(below.min(), Some(-1000.0));
+        assert!(below.max().unwrap() <= 250.0);
+        assert!(above.min().unwrap() >= 247.0);
+        assert_eq!(above.max(), Some(1000.0));
+        assert_eq!(below.nan_count() + above.nan_count(), 1);
+        assert!((below.sum().unwrap() + above.sum().unwrap() - dd.sum().unwrap()).abs() <= 1.0e-6);
+
+        let (below, above) = dd.split_at(-5000.0).unwrap();
+        assert!(below.is_empty());
+        assert_eq!(above.count(), dd.count());
+
+        assert!(dd.split_at(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_remove() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..=100 {
+            dd.add(i as f64);
         }
+        let median = dd.quantile(0.5).unwrap().unwrap();
 
-        assert_eq!(dd.max(), Some(0.755188464));
-        assert_eq!(dd.min(), Some(0.750064549));
-        assert_eq!(dd.count(), 31);
-        assert_eq!(dd.sum(), Some(23.343630625000003));
+        for i in 1..=50 {
+            dd.remove(i as f64);
+        }
+        assert_eq!(dd.count(), 50);
+        assert_eq!(dd.sum(), Some((51..=100).sum::<i32>() as f64));
+        assert!(dd.quantile(0.5).unwrap().unwrap() > median);
+        assert!((dd.quantile(0.5).unwrap().unwrap() - 75.0).abs() <= 75.0 * 0.02);
+        // The min is left as a loose bound
+        assert_eq!(dd.min(), Some(1.0));
 
-        assert!(dd.quantile(0.25).unwrap().is_some());
-        assert!(dd.quantile(0.5).unwrap().is_some());
-        assert!(dd.quantile(0.75).unwrap().is_some());
+        // 90.0 shares its bin with 89.0, and no more than the bin holds is removed
+        dd.remove_n(90.0, 10);
+        assert_eq!(dd.count(), 48);
+        dd.remove(f64::NAN);
+        dd.remove(1.0e9);
+        assert_eq!(dd.count(), 48);
+
+        dd.remove_n(75.0, 5);
+        for i in 51..=100 {
+            dd.remove_n(i as f64, 3);
+        }
+        assert!(dd.is_empty());
+        assert_eq!(dd.min(), None);
+        assert_eq!(dd.sum(), None);
+
+        // A value below the store's range that was never added does not take from its lowest
+        // bin, which holds a different key
+        let mut dd = DDSketch::new(c);
+        dd.add(1000.0);
+        let low = c.value(c.key(1000.0) - 127);
+        dd.add(low);
+        dd.remove(1.0);
+        assert_eq!(dd.count(), 2);
+        assert_eq!(dd.sum(), Some(1000.0 + low));
+        dd.remove(low);
+        assert_eq!(dd.count(), 1);
+
+        // Once the lowest bin has absorbed collapsed keys, removing them takes from it
+        let mut dd = DDSketch::new(Config::new(0.01, 128, 1.0e-9));
+        for i in 0..200 {
+            dd.add(1.05_f64.powi(i));
+        }
+        assert!(dd.collapse_events() > 0);
+        dd.remove(1.0);
+        assert_eq!(dd.count(), 199);
+    }
+
+    #[test]
+    fn test_equality() {
+        let c = Config::defaults();
+        let mut d1 = DDSketch::new(c);
+        let mut d2 = DDSketch::with_expected_range(c, 1.0, 1.0e6);
+
+        assert!(d1 == d2);
+        assert!(d1.approx_eq(&d2, 0.0));
+
+        for i in 1..=1000 {
+            d1.add(i as f64);
+            d2.add(i as f64);
+        }
+        assert!(d1 == d2);
+
+        let mut perturbed = DDSketch::new(c);
+        for i in 1..=1000 {
+            perturbed.add(i as f64 * 1.005);
+        }
+        assert!(d1 != perturbed);
+        assert!(d1.approx_eq(&perturbed, 0.05));
+        assert!(!d1.approx_eq(&perturbed, 0.0));
+
+        assert!(!d1.approx_eq(&DDSketch::new(c), 1.0));
+        let mut other = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+        other.add_all((1..=1000).map(|i| i as f64));
+        assert!(!d1.approx_eq(&other, 1.0));
+    }
+
+    #[test]
+    fn test_extremes_trusted() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+        assert!(dd.extremes_trusted());
+
+        for i in 1..=100 {
+            dd.add(i as f64);
+        }
+        assert!(dd.extremes_trusted());
+
+        // Removing a value that was never added leaves the sketch untouched
+        dd.remove(1.0e6);
+        assert!(dd.extremes_trusted());
+
+        let mut merged = DDSketch::new(c);
+        merged.merge(&dd).unwrap();
+        assert!(merged.extremes_trusted());
+
+        dd.remove(100.0);
+        assert!(!dd.extremes_trusted());
+        merged.merge(&dd).unwrap();
+        assert!(!merged.extremes_trusted());
+
+        let mut pruned = DDSketch::new(c);
+        pruned.add_all(vec![1.0, 2.0, 1000.0]);
+        pruned.retain_bins(|_, _| true);
+        assert!(pruned.extremes_trusted());
+        pruned.retain_bins(|k, _| k < c.key(500.0));
+        assert!(!pruned.extremes_trusted());
+
+        pruned.clear();
+        assert!(pruned.extremes_trusted());
+        pruned.add_key_n(c.key(5.0), 2);
+        assert!(!pruned.extremes_trusted());
+
+        let decoded = DDSketch::from_bytes(&dd.to_bytes()).unwrap();
+        assert!(!decoded.extremes_trusted());
+    }
+
+    #[test]
+    fn test_size_bytes() {
+        let c = Config::new(0.01, 1024, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        let baseline = dd.size_bytes();
+        assert!((128 * 8..2048).contains(&baseline), "baseline {}", baseline);
+
+        dd.add(1.0);
+        assert_eq!(dd.size_bytes(), baseline);
+
+        // Each step of 1.05 lands in a new bin
+        let mut v = 1.0;
+        for _ in 0..500 {
+            v *= 1.05;
+            dd.add(v);
+        }
+        let grown = dd.size_bytes();
+        assert!(grown > baseline + 300 * 8, "grown {}", grown);
+
+        for _ in 0..5000 {
+            v *= 1.05;
+            dd.add(v);
+        }
+        let saturated = dd.size_bytes();
+        assert!(saturated >= 1024 * 8);
+        for _ in 0..5000 {
+            v *= 1.05;
+            dd.add(v);
+        }
+        assert_eq!(dd.size_bytes(), saturated);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_merge_dedup() {
+        let c = Config::defaults();
+        let mut merged = DDSketch::new(c);
+        let mut seen = HashSet::new();
+
+        let mut host = DDSketch::new(c);
+        host.add_all(vec![1.0, 2.0, 3.0]);
+
+        assert!(merged.merge_dedup(&host, 7, &mut seen).unwrap());
+        assert_eq!(merged.count(), 3);
+        assert!(!merged.merge_dedup(&host, 7, &mut seen).unwrap());
+        assert_eq!(merged.count(), 3);
+        assert!(merged.merge_dedup(&host, 8, &mut seen).unwrap());
+        assert_eq!(merged.count(), 6);
+
+        let other = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+        assert!(merged.merge_dedup(&other, 9, &mut seen).is_err());
+        assert!(!seen.contains(&9));
+    }
+
+    #[test]
+    fn test_decay() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        dd.decay(0.5);
+        assert!(dd.is_empty());
+
+        for _ in 0..1000 {
+            dd.add(1000.0);
+        }
+        dd.add(3.0);
+
+        let mut medians = Vec::new();
+        for _ in 0..8 {
+            dd.decay(0.5);
+            for _ in 0..100 {
+                dd.add(1.0);
+            }
+            medians.push(dd.quantile(0.5).unwrap().unwrap());
+        }
+
+        // The single 3.0 rounds away at the first decay, while the burst fades out by halves
+        // and the recent values settle at about twice what is added per interval
+        assert_eq!(dd.count(), 3 + 199);
+        assert!(medians[1] > 900.0);
+        assert!((medians[2] - 1.0).abs() <= 0.01);
+        assert!(medians.windows(2).all(|w| w[1] <= w[0]));
+        assert!(!dd.extremes_trusted());
+        assert_eq!(dd.max(), Some(1000.0));
+    }
+
+    #[test]
+    fn test_collapse_strategy() {
+        let values: Vec<f64> = (0..1000).map(|i| 1.05_f64.powi(i)).collect();
+        let exact = |q: f64| values[(q * 999.0) as usize];
+        let rel = |a: f64, b: f64| (a - b).abs() / b;
+
+        let lowest = Config::new(0.01, 256, 1.0e-9);
+        let highest = lowest.with_collapse_strategy(CollapseStrategy::CollapseHighest);
+        assert_ne!(lowest, highest);
+
+        let mut lo = DDSketch::new(lowest);
+        let mut hi = DDSketch::new(highest);
+        lo.add_all(values.iter().copied());
+        hi.add_all(values.iter().copied());
+        assert!(lo.collapse_events() > 0 && hi.collapse_events() > 0);
+        assert_eq!(hi.length(), 256);
+        assert_eq!(hi.min(), Some(1.0));
+
+        // Collapsing the lowest bins keeps the upper tail exact to within alpha, and the
+        // other way around
+        for q in &[0.9, 0.99] {
+            assert!(rel(lo.quantile(*q).unwrap().unwrap(), exact(*q)) <= 0.01);
+            assert!(rel(hi.quantile(*q).unwrap().unwrap(), exact(*q)) > 0.5);
+        }
+        for q in &[0.01, 0.1] {
+            assert!(rel(hi.quantile(*q).unwrap().unwrap(), exact(*q)) <= 0.01);
+            assert!(rel(lo.quantile(*q).unwrap().unwrap(), exact(*q)) > 0.5);
+        }
+
+        assert!(lo.merge(&hi).is_err());
+        let decoded = DDSketch::from_bytes(&hi.to_bytes()).unwrap();
+        assert_eq!(
+            decoded.config.collapse_strategy(),
+            CollapseStrategy::CollapseHighest
+        );
+        for q in &[0.01, 0.5, 0.99] {
+            assert_eq!(decoded.quantile(*q).unwrap(), hi.quantile(*q).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_add_all() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        let values = vec![1.0, f64::NAN, 2.0, f64::INFINITY, 1.0e6, f64::NAN];
+        let stats = dd.add_all(values);
+
+        assert_eq!(stats.added, 3);
+        assert_eq!(stats.skipped, 3);
+        assert_eq!(stats.bins_before, 128);
+        assert_eq!(stats.bins_after, dd.length());
+        assert!(stats.bins_after > stats.bins_before);
+        assert_eq!(dd.count(), 3);
+        assert_eq!(dd.nan_count(), 2);
+        assert_eq!(dd.inf_count(), 1);
+
+        let stats = dd.add_all((1..10).map(|i| i as f64));
+        assert_eq!(stats.added, 9);
+        assert_eq!(stats.bins_before, stats.bins_after);
+    }
+
+    #[test]
+    fn test_quantile_extremes_are_exact() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..1001 {
+            dd.add(i as f64 + 0.123);
+        }
+
+        // The extremes are exact, while quantiles just inside them come from a bin
+        assert_eq!(dd.quantile(0.0).unwrap(), dd.min_observed());
+        assert_eq!(dd.quantile(1.0).unwrap(), dd.max_observed());
+        assert_eq!(dd.max_observed(), Some(1000.123));
+        assert_eq!(dd.min_observed(), dd.min());
+
+        let near_top = dd.quantile(0.999).unwrap().unwrap();
+        assert_ne!(near_top, 999.123);
+        assert!((near_top - 999.123).abs() <= 999.123 * c.alpha());
+    }
+
+    #[test]
+    fn test_compact() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..101 {
+            dd.add(i as f64);
+        }
+        dd.add(1.0e-6);
+        dd.add(1.0e6);
+
+        let outliers = (c.key(1.0e-6), c.key(1.0e6));
+        dd.retain_bins(|k, _| k != outliers.0 && k != outliers.1);
+
+        let before = dd.clone();
+        let length = dd.length();
+        dd.compact();
+
+        assert!(dd.length() < length / 4);
+        assert_eq!(dd.count(), before.count());
+        assert_eq!(dd.sum(), before.sum());
+        for q in &[0.0, 0.01, 0.25, 0.5, 0.75, 0.99, 1.0] {
+            assert_eq!(dd.quantile(*q).unwrap(), before.quantile(*q).unwrap());
+        }
+
+        // Removing every other value leaves interior gaps, which packing drops
+        let mut gappy = DDSketch::new(c);
+        let values: Vec<f64> = (0..200).map(|i| 1.2_f64.powi(i)).collect();
+        for v in &values {
+            gappy.add(*v);
+        }
+        for v in values.iter().skip(1).step_by(2) {
+            gappy.remove(*v);
+        }
+        let before = gappy.clone();
+        gappy.compact();
+
+        assert!(gappy.length() * 3 < before.length());
+        assert!(gappy.size_bytes() < before.size_bytes());
+        assert_eq!(gappy, before);
+        for q in &[0.0, 0.01, 0.25, 0.5, 0.75, 0.99, 1.0] {
+            assert_eq!(gappy.quantile(*q).unwrap(), before.quantile(*q).unwrap());
+        }
+
+        // Packed bins still merge and take new samples
+        let mut merged = DDSketch::new(c);
+        merged.merge(&gappy).unwrap();
+        assert_eq!(merged, before);
+        gappy.add(values[1]);
+        gappy.remove(values[2]);
+        assert_eq!(gappy.count(), before.count());
+        assert_eq!(gappy.store.count_at(c.key(values[1])), 1);
+
+        dd.retain_bins(|_, _| false);
+        dd.compact();
+        dd.add(42.0);
+        assert_eq!(dd.quantile(0.5).unwrap(), Some(42.0));
+    }
+
+    #[test]
+    fn test_quantile_distance() {
+        let c = Config::defaults();
+        let mut d1 = DDSketch::new(c);
+        let mut d2 = DDSketch::new(c);
+
+        for i in 1..1001 {
+            d1.add(i as f64);
+            d2.add(i as f64 + 100.0);
+        }
+
+        assert_eq!(d1.quantile_distance(&d1.clone(), 100).unwrap(), Some(0.0));
+
+        let distance = d1.quantile_distance(&d2, 100).unwrap().unwrap();
+        assert!(distance > 90.0 && distance < 110.0, "distance {}", distance);
+        assert_eq!(d2.quantile_distance(&d1, 100).unwrap(), Some(distance));
+
+        assert_eq!(d1.quantile_distance(&DDSketch::new(c), 10).unwrap(), None);
+        assert!(matches!(
+            d1.quantile_distance(&d2, 0),
+            Err(DDSketchError::Quantile)
+        ));
+        let other = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+        assert!(matches!(
+            d1.quantile_distance(&other, 10),
+            Err(DDSketchError::Merge)
+        ));
+    }
+
+    #[test]
+    fn test_collapse_events() {
+        let c = Config::new(0.01, 128, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..11 {
+            dd.add(i as f64);
+        }
+        assert_eq!(dd.collapse_events(), 0);
+
+        dd.add(1.0e3);
+        let after_first = dd.collapse_events();
+        assert!(after_first > 0);
+
+        dd.add(1.0e6);
+        assert!(dd.collapse_events() > after_first);
+
+        let mut total = DDSketch::new(c);
+        total.merge(&dd).unwrap();
+        total.merge(&dd).unwrap();
+        assert!(total.collapse_events() >= 2 * dd.collapse_events());
+
+        // Remapping the bins keeps the lifetime count
+        let events = dd.collapse_events();
+        assert!(dd.abs().collapse_events() >= events);
+        dd.scale(2.0);
+        assert!(dd.collapse_events() >= events);
+
+        assert_eq!(dd.count(), 12);
+    }
+
+    #[test]
+    fn test_non_finite_counts() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        dd.add(1.0);
+        dd.add(f64::NAN);
+        dd.add(f64::INFINITY);
+        dd.add(2.0);
+        dd.add(f64::NEG_INFINITY);
+        dd.add(f64::NAN);
+
+        assert_eq!(dd.count(), 2);
+        assert_eq!(dd.nan_count(), 2);
+        assert_eq!(dd.inf_count(), 2);
+        assert_eq!(dd.sum(), Some(3.0));
+        assert_eq!(dd.max(), Some(2.0));
+
+        let mut other = DDSketch::new(c);
+        other.add(f64::NAN);
+        dd.merge(&other).unwrap();
+        assert_eq!(dd.nan_count(), 3);
+    }
+
+    #[test]
+    fn test_quantile_rank_boundary() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        dd.add(1.0);
+        dd.add(1.0);
+        dd.add(10.0);
+        dd.add(10.0);
+
+        // rank 1 + q(n - 1) = 2 is the last value of the lower bin
+        let q_lo = dd.quantile(1.0 / 3.0).unwrap().unwrap();
+        assert!((q_lo - 1.0).abs() <= 0.01);
+
+        let q_hi = dd.quantile(2.0 / 3.0).unwrap().unwrap();
+        assert!((q_hi - 10.0).abs() <= 0.1);
+    }
+
+    #[test]
+    fn test_span_bins() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        assert_eq!(dd.span_bins(), 0);
+
+        dd.add(1.0);
+        assert_eq!(dd.span_bins(), 1);
+
+        dd.add(100.0);
+        assert_eq!(dd.span_bins(), 232);
+        assert_eq!(dd.span_bins(), (c.key(100.0) - c.key(1.0) + 1) as usize);
+
+        dd.add(-100.0);
+        assert_eq!(dd.span_bins(), (c.key(100.0) - c.key(-100.0) + 1) as usize);
+    }
+
+    #[test]
+    fn test_scale() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..101 {
+            dd.add(i as f64);
+        }
+
+        let mut scaled = dd.clone();
+        scaled.scale(1000.0);
+
+        assert_eq!(scaled.count(), dd.count());
+        assert_eq!(scaled.min(), Some(1000.0));
+        assert_eq!(scaled.max(), Some(100_000.0));
+        assert_eq!(scaled.sum(), Some(dd.sum().unwrap() * 1000.0));
+        for q in &[0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            let expected = dd.quantile(*q).unwrap().unwrap() * 1000.0;
+            let actual = scaled.quantile(*q).unwrap().unwrap();
+            assert!((actual - expected).abs() <= expected * 0.03);
+        }
+
+        let mut mirrored = dd.clone();
+        mirrored.scale(-1.0);
+        assert_eq!(mirrored.min(), Some(-100.0));
+        assert_eq!(mirrored.max(), Some(-1.0));
+        // rank 10 of the mirrored values is -91
+        let q = mirrored.quantile(0.1).unwrap().unwrap();
+        assert!((q + 91.0).abs() <= 91.0 * 0.02);
+
+        let mut zeroed = dd.clone();
+        zeroed.scale(0.0);
+        assert_eq!(zeroed.count(), dd.count());
+        assert_eq!(zeroed.quantile(0.5).unwrap(), Some(0.0));
+        assert_eq!(zeroed.sum(), Some(0.0));
+    }
+
+    #[test]
+    fn test_map_monotone() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..101 {
+            dd.add(i as f64);
+        }
+
+        let shifted = dd.map_monotone(|x| x + 10.0);
+
+        assert_eq!(shifted.count(), dd.count());
+        assert_eq!(shifted.min(), Some(11.0));
+        assert_eq!(shifted.max(), Some(110.0));
+        assert!((shifted.mean().unwrap() - 60.5).abs() < 1.0);
+        for q in &[0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            let expected = dd.quantile(*q).unwrap().unwrap() + 10.0;
+            let actual = shifted.quantile(*q).unwrap().unwrap();
+            assert!((actual - expected).abs() <= expected * 0.03);
+        }
+
+        let negated = dd.map_monotone(|x| -x);
+        assert_eq!(negated.min(), Some(-100.0));
+        assert_eq!(negated.max(), Some(-1.0));
+
+        let overflowed = dd.map_monotone(|x| if x > 50.0 { f64::INFINITY } else { x });
+        assert_eq!(overflowed.count() as u64 + overflowed.inf_count(), 100);
+        assert!((overflowed.max().unwrap() - 50.0).abs() <= 0.5);
+    }
+
+    #[test]
+    fn test_retain_bins() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for _ in 0..5 {
+            dd.add(10.0);
+        }
+        for _ in 0..3 {
+            dd.add(1000.0);
+        }
+        dd.add(1.0);
+        dd.add(100_000.0);
+
+        dd.retain_bins(|_, count| count >= 2);
+
+        assert_eq!(dd.count(), 8);
+        let expected = 5.0 * 10.0 + 3.0 * 1000.0;
+        assert!((dd.sum().unwrap() - expected).abs() <= expected * 0.01);
+        assert_eq!(dd.min(), Some(1.0));
+
+        let key = c.key(10.0);
+        dd.retain_bins(|k, _| k != key);
+        assert_eq!(dd.count(), 3);
+
+        dd.retain_bins(|_, _| false);
+        assert_eq!(dd.count(), 0);
+        assert_eq!(dd.sum(), None);
+
+        dd.add(5.0);
+        assert_eq!(dd.min(), Some(5.0));
+        assert_eq!(dd.sum(), Some(5.0));
+    }
+
+    #[test]
+    fn test_diff_since() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..101 {
+            dd.add(i as f64);
+        }
+        let baseline = dd.clone();
+
+        dd.add(0.5);
+        dd.add(50.0);
+        dd.add(5000.0);
+
+        let diff = dd.diff_since(&baseline).unwrap();
+        assert_eq!(diff.bins().len(), 3);
+        assert!(!diff.is_empty());
+
+        let mut restored = baseline.clone();
+        restored.apply_diff(&diff).unwrap();
+
+        assert_eq!(restored.count(), dd.count());
+        assert_eq!(restored.min(), dd.min());
+        assert_eq!(restored.max(), dd.max());
+        assert!((restored.sum().unwrap() - dd.sum().unwrap()).abs() < 1e-9);
+        for q in &[0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            assert_eq!(restored.quantile(*q).unwrap(), dd.quantile(*q).unwrap());
+        }
+
+        assert!(dd.diff_since(&dd).unwrap().is_empty());
+
+        // Rejected samples and loosened extremes carry over too
+        let mut dd = DDSketch::new(c.with_max_value(1.0e6));
+        for i in 1..101 {
+            dd.add(i as f64);
+        }
+        let baseline = dd.clone();
+        dd.add(f64::NAN);
+        dd.add(f64::INFINITY);
+        dd.add(1.0e9);
+        dd.remove(100.0);
+        let diff = dd.diff_since(&baseline).unwrap();
+        let mut restored = baseline.clone();
+        restored.apply_diff(&diff).unwrap();
+        assert_eq!(restored, dd);
+        assert_eq!(restored.nan_count(), 1);
+        assert_eq!(restored.inf_count(), 1);
+        assert_eq!(restored.out_of_range_count(), 1);
+        assert!(!restored.extremes_trusted());
+
+        let mut rejected = dd.clone();
+        rejected.add(f64::NAN);
+        assert!(!rejected.diff_since(&dd).unwrap().is_empty());
+
+        let other = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+        assert!(dd.diff_since(&other).is_err());
+    }
+
+    #[test]
+    fn test_log_centered() {
+        let c = Config::log_centered(0.01, 2048);
+        let mut dd = DDSketch::new(c);
+
+        // Every speedup is paired with the matching slowdown
+        for i in 1..=500 {
+            let x = 1.0 + i as f64 / 100.0;
+            dd.add(x);
+            dd.add(1.0 / x);
+        }
+        assert_eq!(c.key(2.0), -c.key(0.5));
+
+        for q in &[0.01, 0.1, 0.25, 0.4, 0.49] {
+            let lo = dd.quantile(*q).unwrap().unwrap();
+            let hi = dd.quantile(1.0 - q).unwrap().unwrap();
+            assert!((lo * hi - 1.0).abs() <= 2.0 * c.alpha(), "q={}", q);
+        }
+        let median = dd.quantile(0.5).unwrap().unwrap();
+        assert!((median - 1.0).abs() <= 0.02);
+
+        dd.add(0.0);
+        dd.add(-2.0);
+        assert_eq!(dd.count(), 1000);
+        assert_eq!(dd.out_of_range_count(), 2);
+        assert_eq!(dd.zero_count(), 0);
+
+        // Nor are they removed from the lowest bin, which they would otherwise map to
+        dd.add(c.min_value());
+        dd.remove(0.0);
+        dd.remove(-2.0);
+        assert_eq!(dd.count(), 1001);
+        dd.remove(c.min_value());
+        assert_eq!(dd.count(), 1000);
+
+        // Values mapped or re-binned to non-positive values are rejected too
+        let shifted = dd.map_monotone(|x| x - 1.0);
+        assert!(shifted.count() < 1000);
+        assert_eq!(shifted.count() as u64 + shifted.out_of_range_count(), 1002);
+        assert!(shifted.min().unwrap() > 0.0);
+
+        let mut linear = DDSketch::new(Config::new(0.01, 4096, 1.0e-9));
+        linear.add(-1.0);
+        linear.add(0.0);
+        linear.add(2.0);
+        let mut rebinned = DDSketch::new(c);
+        rebinned.merge_rebin(&linear);
+        assert_eq!(rebinned.count(), 1);
+        assert_eq!(rebinned.out_of_range_count(), 2);
+        assert!(rebinned.min().unwrap() > 1.9);
+        assert_eq!(rebinned.max(), Some(2.0));
+        assert!((rebinned.sum().unwrap() - 2.0).abs() <= 2.0 * c.alpha());
+
+        let decoded = DDSketch::from_bytes(&dd.to_bytes()).unwrap();
+        assert!(decoded.config.is_log_centered());
+        assert_eq!(decoded.quantile(0.9).unwrap(), dd.quantile(0.9).unwrap());
+
+        assert!(dd.merge(&DDSketch::new(Config::defaults())).is_err());
     }
 }
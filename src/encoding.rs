@@ -0,0 +1,567 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::config::{CollapseStrategy, Config, Provenance};
+use crate::ddsketch::{DDSketch, DDSketchError};
+use crate::store::Store;
+
+type Result<T> = core::result::Result<T, DDSketchError>;
+
+const VERSION: u8 = 1;
+
+// The binary format is always sparse, regardless of how the store is laid out in memory:
+//
+//   version    u8
+//   config     max_num_bins u32, gamma f64, gamma_ln f64, min_value f64, offset i32,
+//              max_value f64, collapse u8 (0 for the lowest bins, 1 for the highest),
+//              mapping u8 (0 for the default, 1 for log-centered)
+//   summary    min f64, max f64, sum f64, scale_factor f64
+//   rejected   nan_count varint, inf_count varint, out_of_range_count varint
+//   collapses  varint
+//   flags      u8, bit 0 set when the extremes are trusted
+//   num_bins   varint
+//   bins       num_bins x (zigzag varint key delta, varint count)
+//
+// Fixed-width fields are little-endian. Each key is encoded as the delta from the previous key
+// (the first from 0), so the size scales with the number of populated bins rather than the span
+// of keys between them.
+
+/// The summary statistics of a sketch without its bins, for cheaply reporting on a sketch too
+/// large to ship frequently. The fields match the corresponding `DDSketch` accessors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub count: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub sum: Option<f64>,
+    pub mean: Option<f64>,
+}
+
+impl Summary {
+    /// The length of the fixed binary layout used by `to_bytes`
+    pub const ENCODED_LEN: usize = 40;
+
+    /// Encode the summary as the count followed by the min, max, sum and mean, each as 8
+    /// little-endian bytes. Missing values are encoded as NaN.
+    pub fn to_bytes(&self) -> [u8; Summary::ENCODED_LEN] {
+        let mut buf = [0; Summary::ENCODED_LEN];
+
+        buf[0..8].copy_from_slice(&self.count.to_le_bytes());
+        for (i, v) in [self.min, self.max, self.sum, self.mean].iter().enumerate() {
+            let start = 8 * (i + 1);
+            buf[start..start + 8].copy_from_slice(&v.unwrap_or(f64::NAN).to_le_bytes());
+        }
+
+        buf
+    }
+
+    /// Decode a summary previously encoded with `to_bytes`
+    pub fn from_bytes(bytes: &[u8; Summary::ENCODED_LEN]) -> Summary {
+        let field = |i: usize| {
+            let mut b = [0; 8];
+            b.copy_from_slice(&bytes[8 * i..8 * (i + 1)]);
+            b
+        };
+        let value = |i: usize| Some(f64::from_le_bytes(field(i))).filter(|v| !v.is_nan());
+
+        Summary {
+            count: u64::from_le_bytes(field(0)),
+            min: value(1),
+            max: value(2),
+            sum: value(3),
+            mean: value(4),
+        }
+    }
+}
+
+impl DDSketch {
+    /// Returns the summary statistics of the sketch, without its bins
+    pub fn summary(&self) -> Summary {
+        Summary {
+            count: self.count() as u64,
+            min: self.min(),
+            max: self.max(),
+            sum: self.sum(),
+            mean: self.mean(),
+        }
+    }
+
+    /// Encode just the summary statistics of the sketch, for lightweight heartbeats. See
+    /// `Summary::to_bytes` for the layout.
+    pub fn summary_bytes(&self) -> [u8; Summary::ENCODED_LEN] {
+        self.summary().to_bytes()
+    }
+
+    /// Encode the sketch into a compact binary form that can be restored with `from_bytes`.
+    /// Only populated bins are written, so sparse sketches stay small however far apart their
+    /// values are.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        buf.push(VERSION);
+
+        buf.extend_from_slice(&self.config.max_num_bins.to_le_bytes());
+        buf.extend_from_slice(&self.config.gamma.to_le_bytes());
+        buf.extend_from_slice(&self.config.gamma_ln.to_le_bytes());
+        buf.extend_from_slice(&self.config.min_value.to_le_bytes());
+        buf.extend_from_slice(&self.config.offset.to_le_bytes());
+        buf.extend_from_slice(&self.config.max_value.to_le_bytes());
+        buf.push((self.config.collapse == CollapseStrategy::CollapseHighest) as u8);
+        buf.push(self.config.log_centered as u8);
+
+        buf.extend_from_slice(&self.min.to_le_bytes());
+        buf.extend_from_slice(&self.max.to_le_bytes());
+        buf.extend_from_slice(&self.sum.to_le_bytes());
+        buf.extend_from_slice(&self.scale_factor.to_le_bytes());
+
+        put_varint(&mut buf, self.nan_count);
+        put_varint(&mut buf, self.inf_count);
+        put_varint(&mut buf, self.out_of_range_count);
+        put_varint(&mut buf, self.store.collapse_count);
+        buf.push(self.min_max_trusted as u8);
+
+        put_varint(&mut buf, self.store.bins().count() as u64);
+        let mut prev_key = 0;
+        for (key, count) in self.store.bins() {
+            put_varint(&mut buf, zigzag(key as i64 - prev_key as i64));
+            put_varint(&mut buf, count);
+            prev_key = key;
+        }
+
+        buf
+    }
+
+    /// Decode a sketch previously encoded with `to_bytes`. Result is an error, represented as
+    /// DDSketchError::BadFormat, if the input is truncated or otherwise malformed, including
+    /// bins that are empty, out of key order, keyed beyond any value or whose counts overflow,
+    /// and extremes that are not finite or out of order.
+    pub fn from_bytes(bytes: &[u8]) -> Result<DDSketch> {
+        let mut r = Reader { bytes };
+
+        if r.u8()? != VERSION {
+            return Err(DDSketchError::BadFormat);
+        }
+
+        let config = Config {
+            max_num_bins: r.u32()?,
+            gamma: r.f64()?,
+            gamma_ln: r.f64()?,
+            min_value: r.f64()?,
+            offset: r.i32()?,
+            max_value: r.f64()?,
+            collapse: match r.u8()? {
+                0 => CollapseStrategy::CollapseLowest,
+                1 => CollapseStrategy::CollapseHighest,
+                _ => return Err(DDSketchError::BadFormat),
+            },
+            log_centered: match r.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(DDSketchError::BadFormat),
+            },
+            provenance: Provenance::Custom,
+        };
+        if !config.is_valid() {
+            return Err(DDSketchError::BadFormat);
+        }
+
+        let min = r.f64()?;
+        let max = r.f64()?;
+        let sum = r.f64()?;
+        let scale_factor = r.f64()?;
+        let nan_count = r.varint()?;
+        let inf_count = r.varint()?;
+        let out_of_range_count = r.varint()?;
+        let collapse_count = r.varint()?;
+        let flags = r.u8()?;
+        if flags > 1 {
+            return Err(DDSketchError::BadFormat);
+        }
+
+        let num_bins = r.varint()?;
+        let mut bins = Vec::new();
+        let bound = config.max_key_magnitude() as u64;
+        let mut key: i64 = 0;
+        for _ in 0..num_bins {
+            key = key
+                .checked_add(unzigzag(r.varint()?))
+                .filter(|k| k.unsigned_abs() <= bound)
+                .ok_or(DDSketchError::BadFormat)?;
+            bins.push((key as i32, r.varint()?));
+        }
+        // Only populated bins are written, in strictly increasing key order
+        let store = Store::from_sparse(
+            config.max_num_bins as i32,
+            config.collapse,
+            &bins,
+            collapse_count,
+        )
+        .ok_or(DDSketchError::BadFormat)?;
+
+        if !r.bytes.is_empty() {
+            return Err(DDSketchError::BadFormat);
+        }
+
+        // The extremes of a non-empty sketch must be finite and ordered, and an empty one holds
+        // no samples to have extremes or a sum
+        let empty = store.count() == 0;
+        let (min, max, sum) = if empty {
+            (f64::INFINITY, -f64::INFINITY, 0.0)
+        } else if min.is_finite() && max.is_finite() && min <= max && !sum.is_nan() {
+            (min, max, sum)
+        } else {
+            return Err(DDSketchError::BadFormat);
+        };
+
+        Ok(DDSketch {
+            config,
+            store,
+            min,
+            max,
+            sum,
+            nan_count,
+            inf_count,
+            out_of_range_count,
+            scale_factor,
+            min_max_trusted: empty || flags == 1,
+            ..DDSketch::new(config)
+        })
+    }
+
+    /// Render the sketch as pretty-printed JSON for debugging: the config parameters, the
+    /// summary statistics, the rejected sample counters and every populated bin as its key,
+    /// the `[lower, upper]` range of values it covers and its count. Unlike `to_bytes` this is
+    /// meant to be read rather than decoded. Missing or non-finite values are written as null.
+    pub fn to_debug_json(&self) -> String {
+        let mut out = String::new();
+
+        // Writing to a String can not fail
+        let _ = writeln!(out, "{{");
+        let _ = writeln!(out, "  \"config\": {{");
+        let _ = writeln!(
+            out,
+            "    \"alpha\": {},",
+            json_f64(Some(self.config.alpha()))
+        );
+        let _ = writeln!(out, "    \"gamma\": {},", json_f64(Some(self.config.gamma)));
+        let _ = writeln!(
+            out,
+            "    \"min_value\": {},",
+            json_f64(Some(self.config.min_value))
+        );
+        let _ = writeln!(
+            out,
+            "    \"max_value\": {},",
+            json_f64(Some(self.config.max_value))
+        );
+        let _ = writeln!(out, "    \"max_num_bins\": {}", self.config.max_num_bins);
+        let _ = writeln!(out, "  }},");
+        let _ = writeln!(out, "  \"count\": {},", self.count());
+        let _ = writeln!(out, "  \"min\": {},", json_f64(self.min()));
+        let _ = writeln!(out, "  \"max\": {},", json_f64(self.max()));
+        let _ = writeln!(out, "  \"sum\": {},", json_f64(self.sum()));
+        let _ = writeln!(out, "  \"mean\": {},", json_f64(self.mean()));
+        let _ = writeln!(out, "  \"nan_count\": {},", self.nan_count);
+        let _ = writeln!(out, "  \"inf_count\": {},", self.inf_count);
+        let _ = writeln!(
+            out,
+            "  \"out_of_range_count\": {},",
+            self.out_of_range_count
+        );
+        let _ = write!(out, "  \"bins\": [");
+        for (i, (key, count)) in self.store.bins().enumerate() {
+            let _ = write!(
+                out,
+                "{}\n    {{ \"key\": {}, \"value_range\": [{}, {}], \"count\": {} }}",
+                if i == 0 { "" } else { "," },
+                key,
+                json_f64(Some(self.config.lower_bound(key))),
+                json_f64(Some(self.config.upper_bound(key))),
+                count
+            );
+        }
+        if self.count() == 0 {
+            let _ = writeln!(out, "]");
+        } else {
+            let _ = writeln!(out, "\n  ]");
+        }
+        let _ = writeln!(out, "}}");
+
+        out
+    }
+}
+
+fn json_f64(v: Option<f64>) -> String {
+    match v {
+        Some(v) if v.is_finite() => format!("{:?}", v),
+        _ => "null".to_string(),
+    }
+}
+
+pub(crate) fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub(crate) fn unzigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+pub(crate) fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.bytes.len() < N {
+            return Err(DDSketchError::BadFormat);
+        }
+
+        let mut out = [0; N];
+        out.copy_from_slice(&self.bytes[..N]);
+        self.bytes = &self.bytes[N..];
+        Ok(out)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    pub(crate) fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64> {
+        let mut v = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+
+        Err(DDSketchError::BadFormat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{put_varint, zigzag};
+    use crate::{Config, DDSketch, Summary};
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in -50..100 {
+            dd.add(i as f64 * 1.5);
+        }
+        dd.add(f64::NAN);
+
+        let decoded = DDSketch::from_bytes(&dd.to_bytes()).unwrap();
+
+        assert_eq!(decoded.count(), dd.count());
+        assert_eq!(decoded.min(), dd.min());
+        assert_eq!(decoded.max(), dd.max());
+        assert_eq!(decoded.sum(), dd.sum());
+        assert_eq!(decoded.nan_count(), 1);
+        for q in &[0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0] {
+            assert_eq!(decoded.quantile(*q).unwrap(), dd.quantile(*q).unwrap());
+        }
+
+        let bounded = DDSketch::new(c.with_max_value(10.0));
+        assert_eq!(
+            DDSketch::from_bytes(&bounded.to_bytes()).unwrap().config,
+            bounded.config
+        );
+
+        // The decoded config must still be mergeable with the original
+        let mut merged = decoded.clone();
+        assert!(merged.merge(&dd).is_ok());
+
+        let empty = DDSketch::new(c);
+        let decoded = DDSketch::from_bytes(&empty.to_bytes()).unwrap();
+        assert_eq!(decoded.count(), 0);
+        assert_eq!(decoded.quantile(0.5).unwrap(), None);
+    }
+
+    #[test]
+    fn test_bytes_sparse_size() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        dd.add(1.0e-6);
+        dd.add(1.0);
+        dd.add(1.0e6);
+
+        let encoded = dd.to_bytes();
+        let dense = dd.length() * std::mem::size_of::<u64>();
+
+        assert!(dd.length() > 1000);
+        assert!(encoded.len() < 100, "encoded {} bytes", encoded.len());
+        assert!(encoded.len() * 100 < dense);
+    }
+
+    #[test]
+    fn test_summary_round_trip() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        let empty = Summary::from_bytes(&dd.summary_bytes());
+        assert_eq!(empty.count, 0);
+        assert_eq!(empty.min, None);
+        assert_eq!(empty.mean, None);
+
+        for i in 1..101 {
+            dd.add(i as f64 / 4.0);
+        }
+
+        let summary = Summary::from_bytes(&dd.summary_bytes());
+        assert_eq!(summary, dd.summary());
+        assert_eq!(summary.count, dd.count() as u64);
+        assert_eq!(summary.min, dd.min());
+        assert_eq!(summary.max, dd.max());
+        assert_eq!(summary.sum, dd.sum());
+        assert_eq!(summary.mean, dd.mean());
+    }
+
+    #[test]
+    fn test_debug_json() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        let json: serde_json::Value = serde_json::from_str(&dd.to_debug_json()).unwrap();
+        assert_eq!(json["count"], 0);
+        assert!(json["min"].is_null());
+        assert_eq!(json["bins"].as_array().unwrap().len(), 0);
+
+        for v in &[-2.5, 0.0, 1.0e-12, 1.0, 1.0, 300.0] {
+            dd.add(*v);
+        }
+
+        let json: serde_json::Value = serde_json::from_str(&dd.to_debug_json()).unwrap();
+        for key in &["config", "count", "min", "max", "sum", "mean", "bins"] {
+            assert!(json.get(key).is_some(), "missing {}", key);
+        }
+        assert_eq!(json["config"]["max_num_bins"], 2048);
+        assert!((json["config"]["alpha"].as_f64().unwrap() - 0.01).abs() < 1.0e-12);
+        assert!(json["config"]["max_value"].is_null());
+        assert_eq!(json["count"], 6);
+        assert_eq!(json["min"], -2.5);
+
+        let bins = json["bins"].as_array().unwrap();
+        assert_eq!(bins.len(), 4);
+        assert_eq!(bins[2]["count"], 2);
+        let range = bins[3]["value_range"].as_array().unwrap();
+        assert!(range[0].as_f64().unwrap() < 300.0 && range[1].as_f64().unwrap() >= 300.0);
+    }
+
+    #[test]
+    fn test_bytes_bad_extremes() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+        dd.add(1.0);
+
+        // The summary follows the version byte and the 42 bytes of the config
+        let with_summary = |min: f64, max: f64, sum: f64| {
+            let mut bytes = dd.to_bytes();
+            bytes[43..51].copy_from_slice(&min.to_le_bytes());
+            bytes[51..59].copy_from_slice(&max.to_le_bytes());
+            bytes[59..67].copy_from_slice(&sum.to_le_bytes());
+            DDSketch::from_bytes(&bytes)
+        };
+        assert_eq!(with_summary(1.0, 1.0, 1.0).unwrap(), dd);
+        assert!(with_summary(f64::INFINITY, -f64::INFINITY, 1.0).is_err());
+        assert!(with_summary(2.0, 1.0, 1.0).is_err());
+        assert!(with_summary(f64::NAN, 1.0, 1.0).is_err());
+        assert!(with_summary(1.0, 1.0, f64::NAN).is_err());
+
+        // An empty sketch has no extremes to trust, whatever was encoded
+        let empty = DDSketch::new(c);
+        let mut bytes = empty.to_bytes();
+        bytes[43..51].copy_from_slice(&5.0_f64.to_le_bytes());
+        let decoded = DDSketch::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.min(), None);
+        assert_eq!(decoded, empty);
+    }
+
+    #[test]
+    fn test_bytes_malformed() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+        dd.add(1.0);
+
+        let encoded = dd.to_bytes();
+
+        assert!(DDSketch::from_bytes(&[]).is_err());
+        assert!(DDSketch::from_bytes(&encoded[..encoded.len() - 1]).is_err());
+
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(DDSketch::from_bytes(&trailing).is_err());
+
+        let mut bad_version = encoded;
+        bad_version[0] = 0xff;
+        assert!(DDSketch::from_bytes(&bad_version).is_err());
+    }
+
+    #[test]
+    fn test_bytes_uncanonical_bins() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+        dd.add(1.0);
+        dd.add(2.0);
+
+        // Rewrite the bins after the header, given as (key delta, count) pairs
+        let (k1, k2) = (c.key(1.0) as i64, c.key(2.0) as i64);
+        let encoded = dd.to_bytes();
+        let with_bins = |bins: &[(i64, u64)]| {
+            let mut tail = Vec::new();
+            put_varint(&mut tail, bins.len() as u64);
+            for (delta, count) in bins {
+                put_varint(&mut tail, zigzag(*delta));
+                put_varint(&mut tail, *count);
+            }
+            tail
+        };
+        let valid = with_bins(&[(k1, 1), (k2 - k1, 1)]);
+        let header = &encoded[..encoded.len() - valid.len()];
+        assert_eq!(encoded[header.len()..], valid[..]);
+
+        let decode = |bins: &[(i64, u64)]| {
+            let mut bytes = header.to_vec();
+            bytes.extend(with_bins(bins));
+            DDSketch::from_bytes(&bytes)
+        };
+        assert!(decode(&[(k1, 1), (k2 - k1, 1)]).is_ok());
+        // Duplicate, unsorted and empty bins
+        assert!(decode(&[(k1, 1), (0, 1)]).is_err());
+        assert!(decode(&[(k2, 1), (k1 - k2, 1)]).is_err());
+        assert!(decode(&[(k1, 1), (k2 - k1, 0)]).is_err());
+        // Counts, keys and key deltas that would overflow
+        assert!(decode(&[(k1, u64::MAX), (k2 - k1, 1)]).is_err());
+        assert!(decode(&[(i32::MIN as i64, 1)]).is_err());
+        assert!(decode(&[(k1, 1), (i64::MAX, 1)]).is_err());
+        assert!(decode(&[(i64::MAX, 1), (i64::MAX, 1)]).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::config::Config;
+use crate::ddsketch::{DDSketch, DDSketchError};
+
+type Result<T> = core::result::Result<T, DDSketchError>;
+
+/// A `DDSketch` paired with an exact window of the most recent samples. The window gives an
+/// exact median of the last few samples, where a sketch's relative error would matter, while
+/// the sketch keeps the approximate distribution of the whole history.
+#[derive(Clone)]
+pub struct HybridSketch {
+    sketch: DDSketch,
+    recent: VecDeque<f64>,
+    window: usize,
+}
+
+impl HybridSketch {
+    /// Construct a `HybridSketch` keeping the last `window` samples exactly
+    pub fn new(config: Config, window: usize) -> Self {
+        HybridSketch {
+            sketch: DDSketch::new(config),
+            recent: VecDeque::with_capacity(window),
+            window,
+        }
+    }
+
+    /// Add the sample, evicting the oldest sample from the window once it is full. Every
+    /// sample is also added to the sketch right away, so the sketch always covers the samples
+    /// still in the window. Samples the sketch rejects are not kept in the window either.
+    pub fn add(&mut self, v: f64) {
+        let count = self.sketch.count();
+        self.sketch.add(v);
+        if self.window == 0 || self.sketch.count() == count {
+            return;
+        }
+
+        if self.recent.len() == self.window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(v);
+    }
+
+    /// Returns the exact median of the samples in the window, averaging the two middle samples
+    /// if there is an even number of them, or None if the window is empty
+    pub fn recent_median(&self) -> Option<f64> {
+        if self.recent.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<f64> = self.recent.iter().copied().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let mid = sorted.len() / 2;
+        if sorted.len() % 2 == 1 {
+            Some(sorted[mid])
+        } else {
+            Some((sorted[mid - 1] + sorted[mid]) / 2.0)
+        }
+    }
+
+    /// Return the approximate quantile value over every sample added, with the same semantics
+    /// as `DDSketch::quantile`
+    pub fn quantile(&self, q: f64) -> Result<Option<f64>> {
+        self.sketch.quantile(q)
+    }
+
+    /// Returns the sketch of every sample added
+    pub fn sketch(&self) -> &DDSketch {
+        &self.sketch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{Config, HybridSketch};
+
+    #[test]
+    fn test_recent_median() {
+        let c = Config::defaults();
+        let mut hs = HybridSketch::new(c, 64);
+
+        assert_eq!(hs.recent_median(), None);
+        assert_eq!(hs.quantile(0.5).unwrap(), None);
+
+        for i in 0..1000 {
+            hs.add((i * 7 % 1000) as f64 + 0.5);
+        }
+        hs.add(f64::NAN);
+
+        let mut last: Vec<f64> = (936..1000).map(|i| (i * 7 % 1000) as f64 + 0.5).collect();
+        last.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(hs.recent_median(), Some((last[31] + last[32]) / 2.0));
+
+        // The sketch covers the whole history, not just the window
+        hs.add(10000.0);
+        assert_eq!(hs.sketch().count(), 1001);
+        assert_eq!(hs.quantile(1.0).unwrap(), Some(10000.0));
+
+        let median = hs.quantile(0.5).unwrap().unwrap();
+        assert!((median - 500.0).abs() <= 500.0 * 0.02);
+    }
+
+    #[test]
+    fn test_odd_window() {
+        let mut hs = HybridSketch::new(Config::defaults(), 3);
+
+        for v in &[9.0, 1.0, 5.0, 3.0] {
+            hs.add(*v);
+        }
+        assert_eq!(hs.recent_median(), Some(3.0));
+    }
+}
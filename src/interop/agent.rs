@@ -0,0 +1,396 @@
+// Conversion to and from the `Dogsketch` protobuf message the Datadog Agent ships sketches in,
+// behind the `agent-interop` feature:
+//
+//   message Dogsketch {
+//     int64 ts = 1;
+//     int64 cnt = 2;
+//     double min = 3;
+//     double max = 4;
+//     double avg = 5;
+//     double sum = 6;
+//     repeated sint32 k = 7;
+//     repeated uint32 n = 8;
+//   }
+//
+// The message is small enough that it is encoded by hand rather than pulling in a protobuf
+// library. The Agent rounds `log_gamma(v)` to the nearest key and shifts it by a bias derived
+// from `floor(log_gamma(min_value))`, while this crate takes the ceiling and shifts it by an
+// offset derived from the truncation, so the two key spaces are neither aligned nor a fixed
+// distance apart. Bins are therefore converted through their representative values rather
+// than by shifting keys, which compounds the two mappings' relative errors.
+
+use alloc::vec::Vec;
+
+use crate::config::Config;
+use crate::ddsketch::{DDSketch, DDSketchError};
+use crate::encoding::{put_varint, unzigzag, zigzag, Reader};
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use crate::Float;
+
+type Result<T> = core::result::Result<T, DDSketchError>;
+
+const AGENT_EPS: f64 = 1.0 / 128.0;
+const AGENT_MIN_VALUE: f64 = 1.0e-9;
+const AGENT_MAX_KEY: i32 = i16::MAX as i32;
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LEN: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+// The key mapping of the Agent's sketches
+struct AgentMapping {
+    gamma_ln: f64,
+    bias: i32,
+}
+
+impl AgentMapping {
+    fn new() -> Self {
+        let gamma_ln = (2.0 * AGENT_EPS).ln_1p();
+        let emin = (AGENT_MIN_VALUE.ln() / gamma_ln).floor() as i32;
+
+        AgentMapping {
+            gamma_ln,
+            bias: 1 - emin,
+        }
+    }
+
+    fn key(&self, v: f64) -> i32 {
+        if v < 0.0 {
+            -self.key(-v)
+        } else if v < AGENT_MIN_VALUE {
+            0
+        } else {
+            let k = (v.ln() / self.gamma_ln).round() as i32 + self.bias;
+            k.clamp(1, AGENT_MAX_KEY)
+        }
+    }
+
+    fn value(&self, k: i32) -> f64 {
+        if k < 0 {
+            -self.value(-k)
+        } else if k == 0 {
+            0.0
+        } else {
+            ((k - self.bias) as f64 * self.gamma_ln).exp()
+        }
+    }
+}
+
+impl DDSketch {
+    /// Encode the sketch as the Datadog Agent's `Dogsketch` protobuf message, with its bins
+    /// re-keyed to the Agent's mapping. The timestamp is left unset. Each bin is re-keyed by its
+    /// representative value, so a sketch built with a config other than
+    /// `Config::agent_defaults` loses some accuracy on the way.
+    pub fn to_agent_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if self.is_empty() {
+            return buf;
+        }
+
+        let mapping = AgentMapping::new();
+        let mut bins: Vec<(i32, u64)> = Vec::new();
+        for (key, count) in self.store.bins() {
+            let k = mapping.key(self.config.value(key).max(self.min).min(self.max));
+            match bins.last_mut() {
+                Some(last) if last.0 == k => last.1 += count,
+                _ => bins.push((k, count)),
+            }
+        }
+
+        // The Agent counts bins as uint32, so larger counts are spread over repeated keys
+        let mut keys = Vec::new();
+        let mut counts = Vec::new();
+        for (k, mut count) in bins {
+            while count > 0 {
+                let n = count.min(u32::MAX as u64);
+                put_varint(&mut keys, zigzag(k as i64));
+                put_varint(&mut counts, n);
+                count -= n;
+            }
+        }
+
+        put_tag(&mut buf, 2, WIRE_VARINT);
+        put_varint(&mut buf, self.count() as u64);
+        for (field, v) in &[
+            (3, self.min),
+            (4, self.max),
+            (5, self.sum / self.count() as f64),
+        ] {
+            put_tag(&mut buf, *field, WIRE_FIXED64);
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        put_tag(&mut buf, 6, WIRE_FIXED64);
+        buf.extend_from_slice(&self.sum.to_le_bytes());
+        for (field, packed) in &[(7, keys), (8, counts)] {
+            put_tag(&mut buf, *field, WIRE_LEN);
+            put_varint(&mut buf, packed.len() as u64);
+            buf.extend_from_slice(packed);
+        }
+
+        buf
+    }
+
+    /// Decode a Datadog Agent `Dogsketch` protobuf message into a sketch with
+    /// `Config::agent_defaults`, re-keying each of its bins by the bin's representative value.
+    /// The min, max and sum are taken from the message as is, and unknown fields are skipped.
+    ///
+    /// Result is an error, represented as DDSketchError::BadFormat, if the message is malformed,
+    /// its keys and counts do not pair up, its count does not match the sum of its bins, or its
+    /// extremes or sum are not finite.
+    pub fn from_agent_bytes(bytes: &[u8]) -> Result<DDSketch> {
+        let mut r = Reader { bytes };
+        let (mut cnt, mut min, mut max, mut sum) = (0, 0.0, 0.0, 0.0);
+        let mut keys = Vec::new();
+        let mut counts = Vec::new();
+
+        while !r.bytes.is_empty() {
+            let tag = r.varint()?;
+            match (tag >> 3, tag & 7) {
+                (2, WIRE_VARINT) => cnt = r.varint()?,
+                (3, WIRE_FIXED64) => min = r.f64()?,
+                (4, WIRE_FIXED64) => max = r.f64()?,
+                (6, WIRE_FIXED64) => sum = r.f64()?,
+                (7, WIRE_VARINT) => keys.push(sint32(r.varint()?)?),
+                (7, WIRE_LEN) => {
+                    let mut packed = Reader {
+                        bytes: take(&mut r, 0)?,
+                    };
+                    while !packed.bytes.is_empty() {
+                        keys.push(sint32(packed.varint()?)?);
+                    }
+                }
+                (8, WIRE_VARINT) => counts.push(uint32(r.varint()?)?),
+                (8, WIRE_LEN) => {
+                    let mut packed = Reader {
+                        bytes: take(&mut r, 0)?,
+                    };
+                    while !packed.bytes.is_empty() {
+                        counts.push(uint32(packed.varint()?)?);
+                    }
+                }
+                (_, WIRE_VARINT) => {
+                    r.varint()?;
+                }
+                (_, WIRE_FIXED64) => {
+                    take(&mut r, 8)?;
+                }
+                (_, WIRE_LEN) => {
+                    take(&mut r, 0)?;
+                }
+                (_, WIRE_FIXED32) => {
+                    take(&mut r, 4)?;
+                }
+                _ => return Err(DDSketchError::BadFormat),
+            }
+        }
+
+        if keys.len() != counts.len() || counts.iter().sum::<u64>() != cnt {
+            return Err(DDSketchError::BadFormat);
+        }
+        // Keys must be sorted and counts populated. A key only repeats when the previous
+        // count was too large for a uint32 and spilled over, as `to_agent_bytes` writes them.
+        for i in 0..keys.len() {
+            let ordered = i == 0
+                || keys[i] > keys[i - 1]
+                || (keys[i] == keys[i - 1] && counts[i - 1] == u32::MAX as u64);
+            if !ordered || counts[i] == 0 {
+                return Err(DDSketchError::BadFormat);
+            }
+        }
+
+        let config = Config::agent_defaults();
+        let mut sketch = DDSketch::new(config);
+        if cnt == 0 {
+            return Ok(sketch);
+        }
+        if !min.is_finite() || !max.is_finite() || min > max || !sum.is_finite() {
+            return Err(DDSketchError::BadFormat);
+        }
+
+        let mapping = AgentMapping::new();
+        for (k, n) in keys.into_iter().zip(counts) {
+            let v = mapping.value(k).max(min).min(max);
+            sketch.store.add_n(config.key(v), n);
+        }
+        sketch.min = min;
+        sketch.max = max;
+        sketch.sum = sum;
+
+        Ok(sketch)
+    }
+}
+
+fn put_tag(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    put_varint(buf, field << 3 | wire_type);
+}
+
+// Take `len` bytes from the reader, or as many as a length prefix gives when `len` is 0
+fn take<'a>(r: &mut Reader<'a>, len: usize) -> Result<&'a [u8]> {
+    let len = if len == 0 { r.varint()? as usize } else { len };
+    if r.bytes.len() < len {
+        return Err(DDSketchError::BadFormat);
+    }
+
+    let (head, tail) = r.bytes.split_at(len);
+    r.bytes = tail;
+    Ok(head)
+}
+
+fn sint32(v: u64) -> Result<i32> {
+    let v = unzigzag(v);
+    if v < i32::MIN as i64 || v > i32::MAX as i64 {
+        return Err(DDSketchError::BadFormat);
+    }
+    Ok(v as i32)
+}
+
+fn uint32(v: u64) -> Result<u64> {
+    if v > u32::MAX as u64 {
+        return Err(DDSketchError::BadFormat);
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{unzigzag, Reader, WIRE_FIXED64, WIRE_LEN, WIRE_VARINT};
+    use crate::{Config, DDSketch};
+
+    // A Dogsketch message holding 4.0 three times, -0.5 once and 100.0 once, as the Agent's
+    // `pkg/quantile` encodes it: each value maps to the key `round(log_gamma(v)) + bias`, with
+    // the Agent's `gamma = 1 + 1/64` and bias of 1338, giving the keys 1427, -1293 and 1635.
+    // The bytes were assembled by hand from the message definition and that formula rather
+    // than captured from a running Agent.
+    const REFERENCE: &[u8] = &[
+        // cnt = 5
+        0x10, 0x05, //
+        // min = -0.5
+        0x19, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe0, 0xbf, //
+        // max = 100.0
+        0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x59, 0x40, //
+        // avg = 22.3
+        0x29, 0xcd, 0xcc, 0xcc, 0xcc, 0xcc, 0x4c, 0x36, 0x40, //
+        // sum = 111.5
+        0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe0, 0x5b, 0x40, //
+        // k = [-1293, 1427, 1635], packed zigzag varints
+        0x3a, 0x06, 0x99, 0x14, 0xa6, 0x16, 0xc6, 0x19, //
+        // n = [1, 3, 1], packed varints
+        0x42, 0x03, 0x01, 0x03, 0x01,
+    ];
+
+    // Return the `k` and `n` fields of a Dogsketch message as `(key, count)` pairs
+    fn agent_bins(bytes: &[u8]) -> Vec<(i32, u64)> {
+        let mut r = Reader { bytes };
+        let (mut keys, mut counts) = (Vec::new(), Vec::new());
+        while !r.bytes.is_empty() {
+            let tag = r.varint().unwrap();
+            match (tag >> 3, tag & 7) {
+                (field @ 7..=8, WIRE_LEN) => {
+                    let len = r.varint().unwrap() as usize;
+                    let mut packed = Reader {
+                        bytes: &r.bytes[..len],
+                    };
+                    r.bytes = &r.bytes[len..];
+                    while !packed.bytes.is_empty() {
+                        let v = packed.varint().unwrap();
+                        if field == 7 {
+                            keys.push(unzigzag(v) as i32);
+                        } else {
+                            counts.push(v);
+                        }
+                    }
+                }
+                (_, WIRE_FIXED64) => r.bytes = &r.bytes[8..],
+                (_, WIRE_VARINT) => {
+                    r.varint().unwrap();
+                }
+                _ => panic!("unexpected tag {}", tag),
+            }
+        }
+
+        keys.into_iter().zip(counts).collect()
+    }
+
+    #[test]
+    fn test_agent_reference_payload() {
+        let d = DDSketch::from_agent_bytes(REFERENCE).unwrap();
+
+        assert_eq!(d.count(), 5);
+        assert_eq!(d.min(), Some(-0.5));
+        assert_eq!(d.max(), Some(100.0));
+        assert_eq!(d.sum(), Some(111.5));
+        // The center of each Agent bin lies on the upper boundary of one of this crate's bins,
+        // so it decodes into the bin below the one the sample itself maps to
+        let bins: Vec<(i32, u64)> = d.store.bins().collect();
+        assert_eq!(bins, vec![(-1292, 1), (1426, 3), (1634, 1)]);
+        let median = d.quantile(0.5).unwrap().unwrap();
+        assert!((median - 4.0).abs() <= 4.0 * 0.02, "median {}", median);
+
+        // A sketch of the same samples encodes to the same message. Each of this crate's bins
+        // straddles two of the Agent's, so the samples are ones whose representative value
+        // falls in the Agent bin of the sample itself.
+        let mut same = DDSketch::new(Config::agent_defaults());
+        same.add_all(vec![4.0, 4.0, 4.0, -0.5, 100.0]);
+        let bytes = same.to_agent_bytes();
+        assert_eq!(agent_bins(&bytes), vec![(-1293, 1), (1427, 3), (1635, 1)]);
+        assert_eq!(bytes, REFERENCE);
+    }
+
+    #[test]
+    fn test_agent_round_trip() {
+        let mut d = DDSketch::new(Config::defaults());
+        for i in 1..=1000 {
+            d.add(i as f64 * 0.37);
+            d.add(-(i as f64));
+        }
+
+        let decoded = DDSketch::from_agent_bytes(&d.to_agent_bytes()).unwrap();
+        assert_eq!(decoded.count(), d.count());
+        assert_eq!(decoded.min(), d.min());
+        assert_eq!(decoded.max(), d.max());
+        assert_eq!(decoded.sum(), d.sum());
+        for q in &[0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            let a = d.quantile(*q).unwrap().unwrap();
+            let b = decoded.quantile(*q).unwrap().unwrap();
+            // Each conversion adds up to its mapping's relative error
+            assert!((a - b).abs() <= a.abs() * 0.04, "q = {}: {} vs {}", q, a, b);
+        }
+
+        let empty = DDSketch::new(Config::defaults());
+        assert!(empty.to_agent_bytes().is_empty());
+        assert!(DDSketch::from_agent_bytes(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_agent_malformed() {
+        // Truncated in the middle of the max
+        assert!(DDSketch::from_agent_bytes(&REFERENCE[..14]).is_err());
+        // The count no longer matches the bins
+        let mut bytes = REFERENCE.to_vec();
+        bytes[1] = 0x06;
+        assert!(DDSketch::from_agent_bytes(&bytes).is_err());
+        // More keys than counts
+        assert!(DDSketch::from_agent_bytes(&[0x38, 0x02]).is_err());
+        // Duplicate and unsorted keys
+        let duplicate = [
+            0x10, 0x02, 0x3a, 0x04, 0xf4, 0x14, 0xf4, 0x14, 0x42, 0x02, 0x01, 0x01,
+        ];
+        assert!(DDSketch::from_agent_bytes(&duplicate).is_err());
+        let unsorted = [
+            0x10, 0x02, 0x3a, 0x04, 0xc6, 0x19, 0xf4, 0x14, 0x42, 0x02, 0x01, 0x01,
+        ];
+        assert!(DDSketch::from_agent_bytes(&unsorted).is_err());
+        // An infinite min or max
+        for (offset, v) in &[(3, f64::NEG_INFINITY), (12, f64::INFINITY)] {
+            let mut bytes = REFERENCE.to_vec();
+            bytes[*offset..*offset + 8].copy_from_slice(&v.to_le_bytes());
+            assert!(DDSketch::from_agent_bytes(&bytes).is_err());
+        }
+        // An empty bin
+        assert!(DDSketch::from_agent_bytes(&[0x3a, 0x02, 0xf4, 0x14, 0x42, 0x01, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,4 @@
+// Conversions to and from the sketch formats of other DDSketch implementations, each behind its
+// own feature.
+
+mod agent;
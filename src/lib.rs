@@ -42,11 +42,94 @@ d1.merge(&d2);
 assert_eq!(d1.count(), 3);
 ```
 
+The crate supports `no_std` environments that provide `alloc`, by disabling the default `std`
+feature and enabling `libm`, which provides the floating point functions `core` lacks. Without
+`std`, `DDSketch::merge_dedup` and the `std::error::Error` impls of the error types are
+unavailable.
  */
 
-pub use self::config::Config;
-pub use self::ddsketch::{DDSketch, DDSketchError};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("either the `std` or the `libm` feature must be enabled");
+
+#[macro_use]
+extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+pub use self::config::{
+    required_bins, CollapseStrategy, Config, ConfigBuilder, ConfigError, Provenance,
+};
+pub use self::ddsketch::{
+    average_distribution, equal_weight_quantile, quantile_band, AddOutcome, Adequacy, DDSketch,
+    DDSketchError, HealthReport, IngestStats, MergeKind, MergeReport, QuantileResult, Report,
+    SketchDiff,
+};
+pub use self::encoding::Summary;
+pub use self::hybrid::HybridSketch;
+pub use self::shared::SharedDDSketch;
+pub use self::view::{ScaledView, SketchView};
 
 mod config;
 mod ddsketch;
+mod encoding;
+mod hybrid;
+#[cfg(feature = "agent-interop")]
+mod interop;
+#[cfg(feature = "serde")]
+mod serde_support;
+mod shared;
 mod store;
+mod view;
+
+// The methods of `f64` the crate uses that `core` lacks, forwarded to `libm` without `std`.
+// Whenever `std` is linked into the build, as by the test harness or a dependency's `std`
+// feature, its inherent methods take precedence and the trait goes unused.
+#[cfg(not(feature = "std"))]
+#[allow(dead_code)]
+pub(crate) trait Float {
+    fn ln(self) -> f64;
+    fn ln_1p(self) -> f64;
+    fn exp(self) -> f64;
+    fn sqrt(self) -> f64;
+    fn powi(self, n: i32) -> f64;
+    fn floor(self) -> f64;
+    fn ceil(self) -> f64;
+    fn round(self) -> f64;
+}
+
+#[cfg(not(feature = "std"))]
+impl Float for f64 {
+    fn ln(self) -> f64 {
+        libm::log(self)
+    }
+
+    fn ln_1p(self) -> f64 {
+        libm::log1p(self)
+    }
+
+    fn exp(self) -> f64 {
+        libm::exp(self)
+    }
+
+    fn sqrt(self) -> f64 {
+        libm::sqrt(self)
+    }
+
+    fn powi(self, n: i32) -> f64 {
+        libm::pow(self, n as f64)
+    }
+
+    fn floor(self) -> f64 {
+        libm::floor(self)
+    }
+
+    fn ceil(self) -> f64 {
+        libm::ceil(self)
+    }
+
+    fn round(self) -> f64 {
+        libm::round(self)
+    }
+}
@@ -0,0 +1,330 @@
+// Serde support, behind the `serde` feature. Each type is converted to and from a plain
+// representation, so that decoded values can be validated and that sentinel values serialize
+// cleanly: the infinite extremes of an empty sketch and an unbounded `max_value` become None
+// rather than non-finite floats, which formats like JSON can not represent.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CollapseStrategy, Config, Provenance};
+use crate::ddsketch::{DDSketch, DDSketchError};
+use crate::store::Store;
+
+// `gamma_ln` and `offset` are kept rather than recomputed, since recomputing them from gamma
+// can round differently and leave the decoded config unable to merge with the original
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ConfigRepr {
+    max_num_bins: u32,
+    gamma: f64,
+    gamma_ln: f64,
+    min_value: f64,
+    offset: i32,
+    #[serde(default)]
+    max_value: Option<f64>,
+    #[serde(default)]
+    collapse_highest: bool,
+    #[serde(default)]
+    log_centered: bool,
+}
+
+fn collapse_strategy(collapse_highest: bool) -> CollapseStrategy {
+    if collapse_highest {
+        CollapseStrategy::CollapseHighest
+    } else {
+        CollapseStrategy::CollapseLowest
+    }
+}
+
+impl From<Config> for ConfigRepr {
+    fn from(c: Config) -> Self {
+        ConfigRepr {
+            max_num_bins: c.max_num_bins,
+            gamma: c.gamma,
+            gamma_ln: c.gamma_ln,
+            min_value: c.min_value,
+            offset: c.offset,
+            max_value: Some(c.max_value).filter(|v| v.is_finite()),
+            collapse_highest: c.collapse == CollapseStrategy::CollapseHighest,
+            log_centered: c.log_centered,
+        }
+    }
+}
+
+impl TryFrom<ConfigRepr> for Config {
+    type Error = DDSketchError;
+
+    fn try_from(r: ConfigRepr) -> Result<Self, Self::Error> {
+        let config = Config {
+            max_num_bins: r.max_num_bins,
+            gamma: r.gamma,
+            gamma_ln: r.gamma_ln,
+            min_value: r.min_value,
+            offset: r.offset,
+            max_value: r.max_value.unwrap_or(f64::INFINITY),
+            collapse: collapse_strategy(r.collapse_highest),
+            log_centered: r.log_centered,
+            provenance: Provenance::Custom,
+        };
+
+        if config.is_valid() {
+            Ok(config)
+        } else {
+            Err(DDSketchError::BadFormat)
+        }
+    }
+}
+
+// Only the populated bins are kept, as `(key, count)` pairs in ascending key order, so the size
+// scales with the number of populated bins rather than the span of keys between them
+#[derive(Serialize, Deserialize)]
+pub(crate) struct StoreRepr {
+    max_num_bins: i32,
+    bins: Vec<(i32, u64)>,
+    #[serde(default)]
+    collapse_count: u64,
+    #[serde(default)]
+    collapse_highest: bool,
+}
+
+impl From<Store> for StoreRepr {
+    fn from(s: Store) -> Self {
+        StoreRepr {
+            max_num_bins: s.max_num_bins(),
+            bins: s.bins().collect(),
+            collapse_count: s.collapse_count,
+            collapse_highest: s.collapse_strategy() == CollapseStrategy::CollapseHighest,
+        }
+    }
+}
+
+impl TryFrom<StoreRepr> for Store {
+    type Error = DDSketchError;
+
+    fn try_from(r: StoreRepr) -> Result<Self, Self::Error> {
+        Store::from_sparse(
+            r.max_num_bins,
+            collapse_strategy(r.collapse_highest),
+            &r.bins,
+            r.collapse_count,
+        )
+        .ok_or(DDSketchError::BadFormat)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SketchRepr {
+    config: Config,
+    store: Store,
+    min: Option<f64>,
+    max: Option<f64>,
+    sum: f64,
+    #[serde(default)]
+    nan_count: u64,
+    #[serde(default)]
+    inf_count: u64,
+    #[serde(default)]
+    out_of_range_count: u64,
+    #[serde(default)]
+    max_source_bin_count: u64,
+    #[serde(default)]
+    config_changed: bool,
+    #[serde(default = "unscaled")]
+    scale_factor: f64,
+    #[serde(default = "trusted")]
+    min_max_trusted: bool,
+}
+
+fn unscaled() -> f64 {
+    1.0
+}
+
+fn trusted() -> bool {
+    true
+}
+
+impl From<DDSketch> for SketchRepr {
+    fn from(d: DDSketch) -> Self {
+        SketchRepr {
+            config: d.config,
+            min: d.min(),
+            max: d.max(),
+            sum: d.sum,
+            nan_count: d.nan_count,
+            inf_count: d.inf_count,
+            out_of_range_count: d.out_of_range_count,
+            max_source_bin_count: d.max_source_bin_count,
+            config_changed: d.config_changed,
+            scale_factor: d.scale_factor,
+            min_max_trusted: d.min_max_trusted,
+            store: d.store,
+        }
+    }
+}
+
+impl TryFrom<SketchRepr> for DDSketch {
+    type Error = DDSketchError;
+
+    fn try_from(r: SketchRepr) -> Result<Self, Self::Error> {
+        let bound = r.config.max_key_magnitude();
+        if r.store.max_num_bins() != r.config.max_num_bins as i32
+            || r.store.collapse_strategy() != r.config.collapse
+            || r.store.bins().any(|(key, _)| key.unsigned_abs() > bound)
+        {
+            return Err(DDSketchError::BadFormat);
+        }
+
+        let empty = r.store.count() == 0;
+        let (min, max) = match (r.min, r.max) {
+            (_, _) if empty => (f64::INFINITY, -f64::INFINITY),
+            (Some(min), Some(max))
+                if min.is_finite() && max.is_finite() && min <= max && !r.sum.is_nan() =>
+            {
+                (min, max)
+            }
+            _ => return Err(DDSketchError::BadFormat),
+        };
+
+        Ok(DDSketch {
+            config: r.config,
+            store: r.store,
+            min,
+            max,
+            sum: if empty { 0.0 } else { r.sum },
+            nan_count: r.nan_count,
+            inf_count: r.inf_count,
+            out_of_range_count: r.out_of_range_count,
+            max_source_bin_count: r.max_source_bin_count,
+            config_changed: r.config_changed,
+            scale_factor: r.scale_factor,
+            min_max_trusted: empty || r.min_max_trusted,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{Config, DDSketch};
+
+    fn sketch() -> DDSketch {
+        let mut dd = DDSketch::new(Config::defaults());
+        for i in -50..500 {
+            dd.add(i as f64 * 0.75);
+        }
+        dd.add(f64::NAN);
+        dd
+    }
+
+    fn assert_same(decoded: &DDSketch, dd: &DDSketch) {
+        assert_eq!(decoded.config, dd.config);
+        assert_eq!(decoded.count(), dd.count());
+        assert_eq!(decoded.min(), dd.min());
+        assert_eq!(decoded.max(), dd.max());
+        assert_eq!(decoded.sum(), dd.sum());
+        assert_eq!(decoded.nan_count(), dd.nan_count());
+        for q in &[0.0, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0] {
+            assert_eq!(decoded.quantile(*q).unwrap(), dd.quantile(*q).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let dd = sketch();
+        let decoded: DDSketch = serde_json::from_str(&serde_json::to_string(&dd).unwrap()).unwrap();
+        assert_same(&decoded, &dd);
+
+        let mut merged = decoded;
+        assert!(merged.merge(&dd).is_ok());
+
+        let empty = DDSketch::new(Config::defaults().with_max_value(1.0e3));
+        let decoded: DDSketch =
+            serde_json::from_str(&serde_json::to_string(&empty).unwrap()).unwrap();
+        assert_eq!(decoded.config, empty.config);
+        assert!(decoded.is_empty());
+        assert_eq!(decoded.min(), None);
+    }
+
+    #[test]
+    fn test_bincode_round_trip() {
+        let dd = sketch();
+        let decoded: DDSketch = bincode::deserialize(&bincode::serialize(&dd).unwrap()).unwrap();
+        assert_same(&decoded, &dd);
+
+        let config: Config =
+            bincode::deserialize(&bincode::serialize(&dd.config).unwrap()).unwrap();
+        assert_eq!(config, dd.config);
+    }
+
+    #[test]
+    fn test_sparse_size() {
+        let mut dd = DDSketch::new(Config::defaults());
+        dd.add(1.0e-6);
+        dd.add(1.0);
+        dd.add(1.0e6);
+
+        let json = serde_json::to_string(&dd).unwrap();
+        let encoded = bincode::serialize(&dd).unwrap();
+        let dense = dd.length() * std::mem::size_of::<u64>();
+
+        assert!(dd.length() > 1000);
+        assert!(json.len() < 500, "encoded {} bytes", json.len());
+        assert!(encoded.len() < 250, "encoded {} bytes", encoded.len());
+        assert!(encoded.len() * 10 < dense);
+        assert_same(&serde_json::from_str(&json).unwrap(), &dd);
+    }
+
+    #[test]
+    fn test_reject_oversized_store() {
+        let c = Config::new(0.01, 128, 1.0e-9);
+        let mut dd = DDSketch::new(c);
+        dd.add(1.0);
+        dd.add(2.0);
+
+        let span = |n: i32| -> Vec<(i32, u64)> { (0..n).map(|k| (k, 1)).collect() };
+        let mut json: serde_json::Value = serde_json::to_value(&dd).unwrap();
+        json["store"]["bins"] = serde_json::to_value(span(129)).unwrap();
+        assert!(serde_json::from_value::<DDSketch>(json.clone()).is_err());
+
+        json["store"]["bins"] = serde_json::to_value(span(128)).unwrap();
+        assert!(serde_json::from_value::<DDSketch>(json.clone()).is_ok());
+
+        json["config"]["gamma"] = serde_json::Value::from(0.5);
+        assert!(serde_json::from_value::<DDSketch>(json).is_err());
+    }
+
+    #[test]
+    fn test_reject_uncanonical_bins() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+        dd.add(1.0);
+        dd.add(2.0);
+
+        let (k1, k2) = (c.key(1.0), c.key(2.0));
+        let mut json: serde_json::Value = serde_json::to_value(&dd).unwrap();
+        let decode = |json: &mut serde_json::Value, bins: &[(i32, u64)]| {
+            json["store"]["bins"] = serde_json::to_value(bins).unwrap();
+            serde_json::from_value::<DDSketch>(json.clone())
+        };
+        assert!(decode(&mut json, &[(k1, 1), (k2, 1)]).is_ok());
+        // Duplicate, unsorted, empty and overflowing bins
+        assert!(decode(&mut json, &[(k1, 1), (k1, 1)]).is_err());
+        assert!(decode(&mut json, &[(k2, 1), (k1, 1)]).is_err());
+        assert!(decode(&mut json, &[(k1, 1), (k2, 0)]).is_err());
+        // Keys beyond any value and counts that would overflow
+        assert!(decode(&mut json, &[(i32::MIN, 1)]).is_err());
+        assert!(decode(&mut json, &[(k1, u64::MAX), (k2, 1)]).is_err());
+        // Counts summing to exactly u64::MAX fit, whatever the collapse count
+        json["store"]["collapse_count"] = serde_json::Value::from(3);
+        assert!(decode(&mut json, &[(k1, u64::MAX - 1), (k2, 1)]).is_ok());
+        json["store"]["collapse_count"] = serde_json::Value::from(0);
+
+        // Extremes out of order for the populated bins
+        decode(&mut json, &[(k1, 1), (k2, 1)]).unwrap();
+        json["min"] = serde_json::Value::from(2.0);
+        json["max"] = serde_json::Value::from(1.0);
+        assert!(serde_json::from_value::<DDSketch>(json).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use alloc::sync::Arc;
+
+use crate::ddsketch::{DDSketch, DDSketchError};
+
+type Result<T> = core::result::Result<T, DDSketchError>;
+
+/// An immutable `DDSketch` behind an `Arc`, for handing one computed sketch to many readers.
+/// Cloning only bumps a reference count, rather than copying the store.
+#[derive(Clone)]
+pub struct SharedDDSketch {
+    sketch: Arc<DDSketch>,
+}
+
+impl DDSketch {
+    /// Freeze the sketch into a `SharedDDSketch` that can be cloned cheaply
+    pub fn into_shared(self) -> SharedDDSketch {
+        SharedDDSketch {
+            sketch: Arc::new(self),
+        }
+    }
+}
+
+impl SharedDDSketch {
+    /// Return the quantile value for quantiles between 0.0 and 1.0, as `DDSketch::quantile`
+    pub fn quantile(&self, q: f64) -> Result<Option<f64>> {
+        self.sketch.quantile(q)
+    }
+
+    /// Return the fraction of values less than or equal to `v`, as `DDSketch::cdf`
+    pub fn cdf(&self, v: f64) -> Option<f64> {
+        self.sketch.cdf(v)
+    }
+
+    /// Returns the minimum value seen, or None if sketch is empty
+    pub fn min(&self) -> Option<f64> {
+        self.sketch.min()
+    }
+
+    /// Returns the maximum value seen, or None if sketch is empty
+    pub fn max(&self) -> Option<f64> {
+        self.sketch.max()
+    }
+
+    /// Returns the sum of values seen, or None if sketch is empty
+    pub fn sum(&self) -> Option<f64> {
+        self.sketch.sum()
+    }
+
+    /// Returns the number of values added to the sketch
+    pub fn count(&self) -> usize {
+        self.sketch.count()
+    }
+
+    /// Returns the frozen sketch, for queries not mirrored here
+    pub fn sketch(&self) -> &DDSketch {
+        &self.sketch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use std::thread;
+
+    use crate::{Config, DDSketch};
+
+    #[test]
+    fn test_shared_clones() {
+        let c = Config::defaults();
+        let mut dd = DDSketch::new(c);
+
+        for i in 1..1001 {
+            dd.add(i as f64);
+        }
+        let expected = dd.quantile(0.99).unwrap();
+        let shared = dd.into_shared();
+
+        let handles: Vec<_> = (0..16)
+            .map(|_| {
+                let reader = shared.clone();
+                thread::spawn(move || {
+                    (
+                        reader.quantile(0.99).unwrap(),
+                        reader.count(),
+                        reader.cdf(1000.0),
+                    )
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (expected, 1000, Some(1.0)));
+        }
+
+        assert_eq!(shared.min(), Some(1.0));
+        assert_eq!(shared.max(), Some(1000.0));
+        assert_eq!(shared.sum(), Some(500500.0));
+        assert_eq!(shared.sketch().count(), 1000);
+    }
+}
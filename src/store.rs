@@ -1,6 +1,11 @@
-use std::cmp::{max, min};
-use std::fmt;
-use std::ops::RangeFrom;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use core::fmt;
+use core::mem;
+use core::ops::RangeFrom;
+
+use crate::config::CollapseStrategy;
 
 const INITIAL_NUM_BINS: i32 = 128;
 const GROW_LEFT_BY: i32 = 128;
@@ -9,34 +14,231 @@ fn new_vec(size: usize) -> Vec<u64> {
     vec![0; size]
 }
 
+// The bins are shared between clones until either side writes to them, so cloning a store is
+// cheap and a clone serves as a point in time snapshot.
+//
+// The layout always collapses its lowest bins. A store that collapses its highest bins instead
+// keeps every key negated internally, so `min_key`, `max_key` and the bin indices refer to the
+// negated keys, and every method taking or returning a key translates it with `ext`.
+//
+// `compact` may pack the bins, keeping only the populated ones alongside their layout keys, so
+// that empty bins between them take no space. Reads work on either layout, while anything that
+// adds counts first restores the dense one.
 #[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        try_from = "crate::serde_support::StoreRepr",
+        into = "crate::serde_support::StoreRepr"
+    )
+)]
 pub struct Store {
-    bins: Vec<u64>,
+    bins: Arc<Vec<u64>>,
+    // The layout key of each bin while packed, or None while bin `i` holds key `min_key + i`
+    keys: Option<Arc<Vec<i32>>>,
     count: u64,
     min_key: i32,
     max_key: i32,
     max_num_bins: i32,
+    collapse: CollapseStrategy,
+    pub(crate) collapse_count: u64,
 }
 
 impl Store {
+    #[cfg(test)]
     pub fn new(max_num_bins: i32) -> Self {
+        Store::with_strategy(max_num_bins, CollapseStrategy::CollapseLowest)
+    }
+
+    pub fn with_strategy(max_num_bins: i32, collapse: CollapseStrategy) -> Self {
         Store {
-            bins: new_vec(INITIAL_NUM_BINS as usize),
+            bins: Arc::new(new_vec(INITIAL_NUM_BINS as usize)),
+            keys: None,
             count: 0,
             min_key: 0,
-            max_key: 0,
+            max_key: INITIAL_NUM_BINS - 1,
+            max_num_bins,
+            collapse,
+            collapse_count: 0,
+        }
+    }
+
+    /// Construct a store with bins already allocated for keys `min_key..=max_key`, so adding
+    /// keys in that range never reallocates. If the range is wider than `max_num_bins`, only
+    /// the keys at the end that is not collapsed are covered.
+    pub fn with_range(
+        max_num_bins: i32,
+        collapse: CollapseStrategy,
+        min_key: i32,
+        max_key: i32,
+    ) -> Self {
+        let (min_key, max_key) = match collapse {
+            CollapseStrategy::CollapseLowest => (min_key, max_key),
+            CollapseStrategy::CollapseHighest => (-max_key, -min_key),
+        };
+        let min_key = max(min_key, max_key - max_num_bins + 1);
+
+        Store {
+            bins: Arc::new(new_vec((max_key - min_key + 1) as usize)),
+            keys: None,
+            count: 0,
+            min_key,
+            max_key,
             max_num_bins,
+            collapse,
+            collapse_count: 0,
+        }
+    }
+
+    // Translate between the keys of the sketch and the keys of the layout, which are negated
+    // when collapsing the highest bins
+    fn ext(&self, key: i32) -> i32 {
+        match self.collapse {
+            CollapseStrategy::CollapseLowest => key,
+            CollapseStrategy::CollapseHighest => -key,
+        }
+    }
+
+    // The layout key of the bin at index `i`
+    fn layout_key(&self, i: usize) -> i32 {
+        match &self.keys {
+            Some(keys) => keys[i],
+            None => i as i32 + self.min_key,
+        }
+    }
+
+    // The index of the bin for a layout key within `min_key..=max_key`, unless the bins are
+    // packed and the key is not populated
+    fn index(&self, key: i32) -> Option<usize> {
+        match &self.keys {
+            Some(keys) => keys.binary_search(&key).ok(),
+            None => Some((key - self.min_key) as usize),
+        }
+    }
+
+    // Restore the dense layout of packed bins, so that any key in range can be written
+    fn unpack(&mut self) {
+        if let Some(keys) = self.keys.take() {
+            let mut bins = new_vec((self.max_key - self.min_key + 1) as usize);
+            for (key, count) in keys.iter().zip(self.bins.iter()) {
+                bins[(key - self.min_key) as usize] = *count;
+            }
+            self.bins = Arc::new(bins);
+        }
+    }
+
+    // The indices of the bins in ascending order of the sketch's keys
+    fn ascending(&self) -> impl Iterator<Item = usize> {
+        let (len, reversed) = (
+            self.bins.len(),
+            self.collapse == CollapseStrategy::CollapseHighest,
+        );
+        (0..len).map(move |i| if reversed { len - 1 - i } else { i })
+    }
+
+    /// Raise the bin limit to `max_num_bins`. The limit is never lowered, since that would
+    /// require collapsing bins.
+    pub fn widen(&mut self, max_num_bins: i32) {
+        self.max_num_bins = max(self.max_num_bins, max_num_bins);
+    }
+
+    /// Rebuild a store from its bin limit and its non-empty bins as `(key, count)` pairs in
+    /// ascending key order, as `bins` returns them, or None if the keys are not strictly
+    /// increasing, a count is zero, their total overflows or they span more keys than the
+    /// layout can hold.
+    pub fn from_sparse(
+        max_num_bins: i32,
+        collapse: CollapseStrategy,
+        bins: &[(i32, u64)],
+        collapse_count: u64,
+    ) -> Option<Self> {
+        if max_num_bins <= 0 {
+            return None;
         }
+        if bins.windows(2).any(|w| w[0].0 >= w[1].0) || bins.iter().any(|(_, c)| *c == 0) {
+            return None;
+        }
+        let count = bins.iter().try_fold(0u64, |n, (_, c)| n.checked_add(*c))?;
+
+        let mut store = Store {
+            collapse_count,
+            ..Store::with_strategy(max_num_bins, collapse)
+        };
+        let (first, last) = match (bins.first(), bins.last()) {
+            (Some(first), Some(last)) => (first.0, last.0),
+            _ => return Some(store),
+        };
+
+        // Negating i32::MIN would overflow, and a store never grows past the larger of its
+        // limit and its initial allocation
+        let (min_key, max_key) = match collapse {
+            CollapseStrategy::CollapseLowest => (first, last),
+            CollapseStrategy::CollapseHighest => (last.checked_neg()?, first.checked_neg()?),
+        };
+        if max_key as i64 - min_key as i64 >= max(max_num_bins, INITIAL_NUM_BINS) as i64 {
+            return None;
+        }
+
+        let mut layout = new_vec((max_key - min_key + 1) as usize);
+        for (key, n) in bins {
+            layout[(store.ext(*key) - min_key) as usize] = *n;
+        }
+        store.bins = Arc::new(layout);
+        store.count = count;
+        store.min_key = min_key;
+        store.max_key = max_key;
+        Some(store)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn max_num_bins(&self) -> i32 {
+        self.max_num_bins
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn collapse_strategy(&self) -> CollapseStrategy {
+        self.collapse
     }
 
     pub fn length(&self) -> i32 {
         self.bins.len() as i32
     }
 
+    /// Return the number of bins allocated, which may exceed `length` after the store shrinks
+    pub fn capacity(&self) -> usize {
+        self.bins.capacity()
+    }
+
+    /// Return the heap bytes held by the store: the allocated bins plus the shared allocation
+    /// holding the bin vector and its reference counts, and the same for the keys of packed bins
+    pub fn heap_bytes(&self) -> usize {
+        let keys = self.keys.as_ref().map_or(0, |keys| {
+            keys.capacity() * mem::size_of::<i32>()
+                + mem::size_of::<Vec<i32>>()
+                + 2 * mem::size_of::<usize>()
+        });
+
+        self.capacity() * mem::size_of::<u64>()
+            + mem::size_of::<Vec<u64>>()
+            + 2 * mem::size_of::<usize>()
+            + keys
+    }
+
+    #[cfg(test)]
     pub fn add(&mut self, key: i32) {
-        if self.count == 0 {
+        self.add_n(key, 1);
+    }
+
+    /// Add `n` to the bin for `key`, returning true if the store could not grow to cover the
+    /// key and it collapsed into the bin at the collapsed end of the range instead
+    pub fn add_n(&mut self, key: i32, n: u64) -> bool {
+        self.unpack();
+        let key = self.ext(key);
+        // An empty store keeps its layout if it already covers the key
+        if self.count == 0 && (key < self.min_key || key > self.max_key) {
             self.max_key = key;
-            self.min_key = key - (self.length() as i32) + 1;
+            self.min_key = key - self.length() + 1;
         }
         if key < self.min_key {
             self.grow_left(key)
@@ -44,32 +246,204 @@ impl Store {
             self.grow_right(key)
         }
 
+        let collapsed = key < self.min_key;
+        if collapsed {
+            // The store could not grow far enough, so the key collapses into the lowest bin
+            self.collapse_count += 1;
+        }
         let idx = max(key - self.min_key, 0) as usize;
 
-        self.bins[idx] += 1;
-        self.count += 1;
+        Arc::make_mut(&mut self.bins)[idx] += n;
+        self.count += n;
+        collapsed
+    }
+
+    /// Remove up to `n` from the bin for `key`, returning how many were actually removed. Keys
+    /// beyond the collapsed end of the store's range map to the bin at that end, the same way
+    /// `add_n` collapses them, but only once that end has absorbed collapsed keys. Until then
+    /// the bin holds only its own key, and nothing is removed for a key that was never added.
+    pub fn remove_n(&mut self, key: i32, n: u64) -> u64 {
+        let key = self.ext(key);
+        if self.count == 0 || key > self.max_key {
+            return 0;
+        }
+        let saturated = self.max_key as i64 - self.min_key as i64 + 1 >= self.max_num_bins as i64;
+        if key < self.min_key && self.collapse_count == 0 && !saturated {
+            return 0;
+        }
+
+        let idx = match self.index(max(key, self.min_key)) {
+            Some(idx) => idx,
+            None => return 0,
+        };
+        let removed = min(self.bins[idx], n);
+
+        Arc::make_mut(&mut self.bins)[idx] -= removed;
+        self.count -= removed;
+        removed
+    }
+
+    /// Return the count held by the bin for `key`
+    pub fn count_at(&self, key: i32) -> u64 {
+        let key = self.ext(key);
+        if self.count == 0 || key < self.min_key || key > self.max_key {
+            return 0;
+        }
+
+        self.index(key).map_or(0, |idx| self.bins[idx])
+    }
+
+    /// Clear every non-empty bin for which `keep` returns false
+    pub fn retain<F: FnMut(i32, u64) -> bool>(&mut self, mut keep: F) {
+        for i in 0..self.bins.len() {
+            let count = self.bins[i];
+            if count > 0 && !keep(self.ext(self.layout_key(i)), count) {
+                Arc::make_mut(&mut self.bins)[i] = 0;
+                self.count -= count;
+            }
+        }
+    }
+
+    /// Multiply every bin's count by `factor`, rounding down, so bins may empty out
+    pub fn scale_counts(&mut self, factor: f64) {
+        let bins = Arc::make_mut(&mut self.bins);
+        let mut count = 0;
+        for c in bins.iter_mut().filter(|c| **c > 0) {
+            *c = (*c as f64 * factor) as u64;
+            count += *c;
+        }
+        self.count = count;
+    }
+
+    /// Build a new store with the same bin limit, where each bin's count is moved to the key
+    /// returned by `f`. Counts of bins mapped to the same key are combined. The new store keeps
+    /// this one's collapse count, plus any collapses its remapped keys cause.
+    pub fn remap<F: Fn(i32) -> i32>(&self, f: F) -> Store {
+        let mut store = Store {
+            collapse_count: self.collapse_count,
+            ..Store::with_strategy(self.max_num_bins, self.collapse)
+        };
+        for (key, count) in self.bins() {
+            store.add_n(f(key), count);
+        }
+        store
+    }
+
+    /// Empty every bin, keeping the allocation and layout so the store can be reused
+    pub fn clear(&mut self) {
+        Arc::make_mut(&mut self.bins)
+            .iter_mut()
+            .for_each(|count| *count = 0);
+        self.count = 0;
+        self.collapse_count = 0;
+    }
+
+    /// Reallocate the bins to hold only the populated ones, dropping empty bins at either end.
+    /// If packing the populated bins alongside their keys takes less space than a dense span
+    /// from the lowest to the highest, the empty bins between them are dropped as well.
+    pub fn compact(&mut self) {
+        let populated: Vec<(i32, u64)> = (0..self.bins.len())
+            .filter(|i| self.bins[*i] > 0)
+            .map(|i| (self.layout_key(i), self.bins[i]))
+            .collect();
+
+        match (populated.first(), populated.last()) {
+            (Some(first), Some(last)) => {
+                self.min_key = first.0;
+                self.max_key = last.0;
+                let span = (self.max_key - self.min_key + 1) as usize;
+                let packed = populated.len() * (mem::size_of::<u64>() + mem::size_of::<i32>());
+                if packed < span * mem::size_of::<u64>() {
+                    self.keys = Some(Arc::new(populated.iter().map(|(k, _)| *k).collect()));
+                    self.bins = Arc::new(populated.iter().map(|(_, c)| *c).collect());
+                } else {
+                    self.keys = None;
+                    let mut bins = new_vec(span);
+                    for (key, count) in populated {
+                        bins[(key - self.min_key) as usize] = count;
+                    }
+                    self.bins = Arc::new(bins);
+                }
+            }
+            _ => {
+                self.keys = None;
+                self.bins = Arc::new(new_vec(INITIAL_NUM_BINS as usize));
+                self.min_key = 0;
+                self.max_key = INITIAL_NUM_BINS - 1;
+            }
+        }
+    }
+
+    /// Iterate over the non-empty bins as `(key, count)` pairs, in ascending key order
+    pub fn bins(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        self.ascending()
+            .filter(move |i| self.bins[*i] > 0)
+            .map(move |i| (self.ext(self.layout_key(i)), self.bins[i]))
     }
 
+    /// Return the key of the bin holding the value of the given 1-based rank, i.e. the first bin
+    /// whose cumulative count reaches `rank`. When `rank` lands exactly on the boundary between
+    /// two bins it is owned by the lower one, matching the paper's definition of the q-quantile
+    /// as the value of rank `1 + q(n - 1)` in sorted order.
     pub fn key_at_rank(&self, rank: u64) -> i32 {
         let mut n = 0;
-        for (i, bin) in self.bins.iter().enumerate() {
-            n += *bin;
+        for i in self.ascending() {
+            n += self.bins[i];
             if n >= rank {
-                return i as i32 + self.min_key;
+                return self.ext(self.layout_key(i));
+            }
+        }
+
+        self.highest_key()
+    }
+
+    /// Return the key for each of the given ranks, as `key_at_rank` would, in a single pass
+    /// over the bins. The ranks must be in ascending order.
+    pub fn keys_at_ranks(&self, ranks: &[u64]) -> Vec<i32> {
+        let mut keys = Vec::with_capacity(ranks.len());
+        let mut n = 0;
+        for i in self.ascending() {
+            n += self.bins[i];
+            while keys.len() < ranks.len() && n >= ranks[keys.len()] {
+                keys.push(self.ext(self.layout_key(i)));
+            }
+            if keys.len() == ranks.len() {
+                return keys;
             }
         }
 
-        self.max_key
+        keys.resize(ranks.len(), self.highest_key());
+        keys
+    }
+
+    // The highest key the layout covers, in the sketch's keys
+    fn highest_key(&self) -> i32 {
+        match self.collapse {
+            CollapseStrategy::CollapseLowest => self.max_key,
+            CollapseStrategy::CollapseHighest => -self.min_key,
+        }
     }
 
     fn grow_left(&mut self, key: i32) {
-        if self.min_key < key || self.length() >= self.max_num_bins {
+        if self.min_key < key {
+            return;
+        }
+        if self.length() >= self.max_num_bins {
+            // Slide the range down over any empty bins above the highest populated one, so the
+            // key is only collapsed if the populated bins leave no room for it
+            let top = self.bins.iter().rposition(|count| *count > 0).unwrap_or(0) as i32;
+            let shift = min(self.min_key - key, self.length() - 1 - top);
+            if shift > 0 {
+                Arc::make_mut(&mut self.bins).rotate_right(shift as usize);
+                self.min_key -= shift;
+                self.max_key -= shift;
+            }
             return;
         }
 
         let mut min_key;
-        if self.max_key - key >= self.max_num_bins as i32 {
-            min_key = self.max_key - (self.max_num_bins as i32) + 1
+        if self.max_key - key >= self.max_num_bins {
+            min_key = self.max_key - self.max_num_bins + 1
         } else {
             min_key = self.min_key;
 
@@ -82,7 +456,7 @@ impl Store {
         let mut tmp_bins = new_vec((self.max_key - min_key + 1) as usize);
         let tmp_slice = &mut tmp_bins[self.convert_range((self.min_key - min_key)..)];
         tmp_slice.copy_from_slice(&self.bins);
-        self.bins = tmp_bins;
+        self.bins = Arc::new(tmp_bins);
         self.min_key = min_key;
     }
 
@@ -92,10 +466,14 @@ impl Store {
         }
 
         if key - self.max_key >= self.max_num_bins {
-            self.bins = new_vec(self.max_num_bins as usize);
+            let mut tmp_bins = new_vec(self.max_num_bins as usize);
+            tmp_bins[0] = self.count;
+            self.bins = Arc::new(tmp_bins);
             self.max_key = key;
             self.min_key = key - self.max_num_bins + 1;
-            self.bins[0] = self.count
+            if self.count > 0 {
+                self.collapse_count += 1;
+            }
         } else if key - self.min_key >= self.max_num_bins {
             let min_key = key - self.max_num_bins + 1;
 
@@ -109,22 +487,26 @@ impl Store {
                 let src_slice = &self.bins[self.convert_range((min_key - self.min_key)..)];
                 let tmp_slice = &mut tmp_bins[..src_slice.len()];
                 tmp_slice.copy_from_slice(src_slice);
-                self.bins = tmp_bins;
+                self.bins = Arc::new(tmp_bins);
             } else {
-                self.bins.drain(0..((min_key - self.min_key) as usize));
+                let bins = Arc::make_mut(&mut self.bins);
+                bins.drain(0..((min_key - self.min_key) as usize));
                 for _i in (self.max_key - min_key + 1)..self.max_num_bins {
-                    self.bins.push(0);
+                    bins.push(0);
                 }
             }
             self.max_key = key;
             self.min_key = min_key;
-            self.bins[0] += n;
+            Arc::make_mut(&mut self.bins)[0] += n;
+            if n > 0 {
+                self.collapse_count += 1;
+            }
         } else {
             let new_sz = (key - self.min_key + 1) as usize;
             let mut tmp_bins = new_vec(new_sz);
             let tmp_slice = &mut tmp_bins[0..self.bins.len()];
             tmp_slice.copy_from_slice(&self.bins);
-            self.bins = tmp_bins;
+            self.bins = Arc::new(tmp_bins);
             self.max_key = key;
         }
     }
@@ -137,6 +519,16 @@ impl Store {
         if o.count == 0 {
             return;
         }
+        self.unpack();
+
+        // The layouts do not line up, or the other's bins are packed, so add them one by one
+        if o.collapse != self.collapse || o.keys.is_some() {
+            for (key, count) in o.bins() {
+                self.add_n(key, count);
+            }
+            self.collapse_count += o.collapse_count;
+            return;
+        }
 
         if self.count == 0 {
             self.copy(o);
@@ -147,31 +539,39 @@ impl Store {
             if o.min_key < self.min_key {
                 self.grow_left(o.min_key)
             }
-            for i in max(self.min_key, o.min_key)..(o.max_key + 1) {
-                self.bins[(i - self.min_key) as usize] += o.bins[(i - o.min_key) as usize];
+            let min_key = self.min_key;
+            let bins = Arc::make_mut(&mut self.bins);
+            for i in max(min_key, o.min_key)..(o.max_key + 1) {
+                bins[(i - min_key) as usize] += o.bins[(i - o.min_key) as usize];
             }
             let mut n = 0;
-            for i in o.min_key..self.min_key {
+            for i in o.min_key..min(min_key, o.max_key + 1) {
                 n += o.bins[(i - o.min_key) as usize];
             }
-            self.bins[0] += n;
+            bins[0] += n;
+            if n > 0 {
+                self.collapse_count += 1;
+            }
         } else {
             if o.min_key < self.min_key {
-                let mut tmp_bins = o.bins.clone();
+                let mut tmp_bins = o.bins.to_vec();
                 for i in self.min_key..(self.max_key + 1) {
                     tmp_bins[(i - o.min_key) as usize] += self.bins[(i - self.min_key) as usize];
                 }
-                self.bins = tmp_bins;
+                self.bins = Arc::new(tmp_bins);
                 self.max_key = o.max_key;
                 self.min_key = o.min_key
             } else {
                 self.grow_right(o.max_key);
+                let min_key = self.min_key;
+                let bins = Arc::make_mut(&mut self.bins);
                 for i in o.min_key..(o.max_key + 1) {
-                    self.bins[(i - self.min_key) as usize] += o.bins[(i - o.min_key) as usize];
+                    bins[(i - min_key) as usize] += o.bins[(i - o.min_key) as usize];
                 }
             }
         }
         self.count += o.count;
+        self.collapse_count += o.collapse_count;
     }
 
     fn copy(&mut self, o: &Store) {
@@ -179,6 +579,7 @@ impl Store {
         self.min_key = o.min_key;
         self.max_key = o.max_key;
         self.count = o.count;
+        self.collapse_count += o.collapse_count;
     }
 
     fn convert_range(&self, range: RangeFrom<i32>) -> RangeFrom<usize> {
@@ -198,7 +599,7 @@ impl fmt::Debug for Store {
                     f,
                     "[{}] {}: {}, ",
                     i,
-                    (i as i32) + self.min_key,
+                    self.ext(self.layout_key(i)),
                     self.bins[i]
                 )?;
             }
@@ -213,6 +614,9 @@ impl fmt::Debug for Store {
 
 #[cfg(test)]
 mod tests {
+    use alloc::vec::Vec;
+
+    use crate::config::CollapseStrategy;
     use crate::store::Store;
 
     #[test]
@@ -228,8 +632,179 @@ mod tests {
     fn test_simple_store_rev() {
         let mut s = Store::new(2048);
 
-        for i in 2048..0 {
+        for i in (0..2048).rev() {
+            s.add(i);
+        }
+    }
+
+    #[test]
+    fn test_key_at_rank_boundary() {
+        let mut s = Store::new(2048);
+
+        s.add_n(10, 2);
+        s.add_n(20, 2);
+
+        assert_eq!(s.key_at_rank(1), 10);
+        assert_eq!(s.key_at_rank(2), 10);
+        assert_eq!(s.key_at_rank(3), 20);
+        assert_eq!(s.key_at_rank(4), 20);
+    }
+
+    #[test]
+    fn test_keys_at_ranks() {
+        let mut s = Store::new(2048);
+
+        s.add_n(10, 2);
+        s.add_n(20, 2);
+        s.add_n(25, 1);
+
+        let ranks = [1, 2, 3, 3, 5, 9];
+        let keys = s.keys_at_ranks(&ranks);
+        let expected: Vec<i32> = ranks.iter().map(|r| s.key_at_rank(*r)).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_collapse_count() {
+        let mut s = Store::new(128);
+
+        for i in 0..128 {
             s.add(i);
         }
+        assert_eq!(s.collapse_count, 0);
+
+        // Growing right past the limit folds the lowest bin
+        s.add(128);
+        assert_eq!(s.collapse_count, 1);
+
+        // A key below the saturated range lands in the lowest bin
+        s.add(-10);
+        assert_eq!(s.collapse_count, 2);
+
+        let mut o = Store::new(128);
+        o.add(5000);
+        o.merge(&s);
+        assert_eq!(o.collapse_count, 3);
+    }
+
+    #[test]
+    fn test_grow_left_when_saturated() {
+        let mut s = Store::new(128);
+
+        // The initial layout has no room below 0, but its empty upper bins can make some
+        s.add(0);
+        s.add(-1);
+        s.add(-100);
+        assert_eq!(s.collapse_count, 0);
+        assert_eq!(
+            s.bins().collect::<Vec<_>>(),
+            vec![(-100, 1), (-1, 1), (0, 1)]
+        );
+
+        s.add(-200);
+        assert_eq!(s.collapse_count, 1);
+        assert_eq!(s.key_at_rank(1), -127);
+    }
+
+    #[test]
+    fn test_collapse_highest() {
+        let mut s = Store::with_strategy(128, CollapseStrategy::CollapseHighest);
+
+        for i in 0..200 {
+            s.add(i);
+        }
+        s.add(-3);
+
+        // Keys beyond the 128 lowest seen land in the highest bin, which -3 pushed down to 124
+        let bins: Vec<_> = s.bins().collect();
+        assert_eq!(bins.len(), 126);
+        assert_eq!(bins[0], (-3, 1));
+        assert_eq!(bins[1], (0, 1));
+        assert_eq!(bins[125], (124, 76));
+        assert!(s.collapse_count > 0);
+        assert_eq!(s.count(), 201);
+        assert_eq!(s.key_at_rank(1), -3);
+        assert_eq!(s.key_at_rank(201), 124);
+        assert_eq!(s.keys_at_ranks(&[1, 2, 130, 500]), vec![-3, 0, 124, 124]);
+
+        assert_eq!(s.remove_n(3000, 4), 4);
+        assert_eq!(s.count_at(124), 72);
+
+        // Merging a store with the other strategy re-adds its bins
+        let mut o = Store::new(128);
+        o.add(-3);
+        o.add(1);
+        s.merge(&o);
+        assert_eq!(s.count_at(-3), 2);
+        assert_eq!(s.count_at(1), 2);
+        assert_eq!(s.count(), 199);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut s = Store::new(2048);
+
+        s.add_n(10, 2);
+        s.add_n(20, 3);
+        s.add(1500);
+        s.retain(|key, _| key != 1500);
+        s.compact();
+
+        // Two populated bins are smaller packed than as the dense span between them
+        assert_eq!(s.length(), 2);
+        assert_eq!(s.count(), 5);
+        assert_eq!(s.bins().collect::<Vec<_>>(), vec![(10, 2), (20, 3)]);
+        assert_eq!(s.count_at(15), 0);
+        assert_eq!(s.count_at(20), 3);
+        assert_eq!(s.key_at_rank(3), 20);
+
+        s.add(-5);
+        assert_eq!(s.key_at_rank(1), -5);
+        assert_eq!(
+            s.bins().collect::<Vec<_>>(),
+            vec![(-5, 1), (10, 2), (20, 3)]
+        );
+
+        // Bins without gaps stay dense
+        let mut s = Store::new(2048);
+        for key in 10..20 {
+            s.add(key);
+        }
+        s.add(1500);
+        s.retain(|key, _| key != 1500);
+        s.compact();
+        assert_eq!(s.length(), 10);
+    }
+
+    #[test]
+    fn test_clone_is_independent() {
+        let mut s = Store::new(2048);
+        s.add_n(10, 2);
+
+        let snapshot = s.clone();
+        s.add_n(10, 3);
+        s.add(1000);
+        s.remove_n(10, 1);
+
+        assert_eq!(snapshot.count(), 2);
+        assert_eq!(snapshot.count_at(10), 2);
+        assert_eq!(s.count_at(10), 4);
+    }
+
+    #[test]
+    fn test_merge_below_saturated() {
+        let mut s1 = Store::new(128);
+        let mut s2 = Store::new(128);
+
+        for i in 1000..1200 {
+            s1.add(i);
+        }
+        for i in 0..10 {
+            s2.add(i);
+        }
+
+        s1.merge(&s2);
+        assert_eq!(s1.count(), 210);
+        assert_eq!(s1.key_at_rank(1), s1.min_key);
     }
 }
@@ -0,0 +1,327 @@
+use alloc::vec::Vec;
+
+use crate::config::Config;
+use crate::ddsketch::{DDSketch, DDSketchError};
+
+type Result<T> = core::result::Result<T, DDSketchError>;
+
+/// A read-only union of several sketches that share a `Config`. Queries walk the underlying
+/// stores on demand, so no merged sketch is ever allocated. Results match those of an eager
+/// `merge` of the same sketches, as long as that merge would not have needed to collapse bins
+/// to stay within `max_num_bins`.
+#[derive(Clone, Copy)]
+pub struct SketchView<'a> {
+    sketches: &'a [&'a DDSketch],
+}
+
+impl<'a> SketchView<'a> {
+    /// Construct a view over `sketches`. Result is an error, represented as
+    /// DDSketchError::Merge, if the sketches do not all share the same config.
+    pub fn new(sketches: &'a [&'a DDSketch]) -> Result<Self> {
+        if let Some(first) = sketches.first() {
+            if sketches.iter().any(|s| s.config != first.config) {
+                return Err(DDSketchError::Merge);
+            }
+        }
+
+        Ok(SketchView { sketches })
+    }
+
+    /// Return the quantile value of the union for quantiles between 0.0 and 1.0, with the same
+    /// semantics as `DDSketch::quantile`.
+    pub fn quantile(&self, q: f64) -> Result<Option<f64>> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(DDSketchError::Quantile);
+        }
+
+        let (min, max) = match (self.min(), self.max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Ok(None),
+        };
+
+        if q == 0.0 {
+            return Ok(Some(min));
+        } else if q == 1.0 {
+            return Ok(Some(max));
+        }
+
+        let rank = (q * ((self.count() - 1) as f64) + 1.0) as u64;
+        let mut n = 0;
+        let mut key = 0;
+        self.walk(|k, count| {
+            n += count;
+            key = k;
+            n < rank
+        });
+
+        let quantile = self.config().value(key);
+
+        Ok(Some(quantile.max(min).min(max)))
+    }
+
+    /// Return the approximate fraction of values in the union that are less than or equal to
+    /// `v`, with the same semantics as `DDSketch::cdf`.
+    pub fn cdf(&self, v: f64) -> Option<f64> {
+        let (min, max) = match (self.min(), self.max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return None,
+        };
+
+        if v < min {
+            return Some(0.0);
+        } else if v >= max {
+            return Some(1.0);
+        }
+
+        let key = self.config().key(v);
+        let mut n = 0;
+        self.walk(|k, count| {
+            if k <= key {
+                n += count;
+            }
+            k < key
+        });
+
+        Some((n as f64 / self.count() as f64).min(1.0))
+    }
+
+    /// Returns the minimum value seen across all sketches, or None if they are all empty
+    pub fn min(&self) -> Option<f64> {
+        self.sketches
+            .iter()
+            .filter_map(|s| s.min())
+            .fold(None, |acc: Option<f64>, v| {
+                Some(acc.map_or(v, |m| m.min(v)))
+            })
+    }
+
+    /// Returns the maximum value seen across all sketches, or None if they are all empty
+    pub fn max(&self) -> Option<f64> {
+        self.sketches
+            .iter()
+            .filter_map(|s| s.max())
+            .fold(None, |acc: Option<f64>, v| {
+                Some(acc.map_or(v, |m| m.max(v)))
+            })
+    }
+
+    /// Returns the sum of values seen across all sketches, or None if they are all empty
+    pub fn sum(&self) -> Option<f64> {
+        self.sketches
+            .iter()
+            .filter_map(|s| s.sum())
+            .fold(None, |acc: Option<f64>, v| Some(acc.unwrap_or(0.0) + v))
+    }
+
+    /// Returns the number of values added across all sketches
+    pub fn count(&self) -> usize {
+        self.sketches.iter().map(|s| s.count()).sum()
+    }
+
+    fn config(&self) -> &Config {
+        &self.sketches[0].config
+    }
+
+    // Visit the bins of the union in ascending key order, summing the counts of bins that share
+    // a key, until `f` returns false.
+    fn walk<F: FnMut(i32, u64) -> bool>(&self, mut f: F) {
+        let mut iters: Vec<_> = self
+            .sketches
+            .iter()
+            .map(|s| s.store.bins().peekable())
+            .collect();
+
+        loop {
+            let key = match iters
+                .iter_mut()
+                .filter_map(|it| it.peek())
+                .map(|b| b.0)
+                .min()
+            {
+                Some(key) => key,
+                None => return,
+            };
+
+            let mut count = 0;
+            for it in iters.iter_mut() {
+                if let Some(&(k, n)) = it.peek() {
+                    if k == key {
+                        count += n;
+                        it.next();
+                    }
+                }
+            }
+
+            if !f(key, count) {
+                return;
+            }
+        }
+    }
+}
+
+/// A read-only view of a sketch built from a sample of a larger population, as returned by
+/// `DDSketch::with_scaled_count`. Counts and the sum are multiplied by the scale to estimate the
+/// population's, while quantiles, the extremes and `cdf`, being scale-invariant, are those of
+/// the sketch itself.
+#[derive(Clone, Copy)]
+pub struct ScaledView<'a> {
+    sketch: &'a DDSketch,
+    scale: f64,
+}
+
+impl<'a> ScaledView<'a> {
+    /// Return the quantile value for `q`, which is the same as `DDSketch::quantile`
+    pub fn quantile(&self, q: f64) -> Result<Option<f64>> {
+        self.sketch.quantile(q)
+    }
+
+    /// Return the approximate fraction of values that are less than or equal to `v`, which is
+    /// the same as `DDSketch::cdf`
+    pub fn cdf(&self, v: f64) -> Option<f64> {
+        self.sketch.cdf(v)
+    }
+
+    /// Returns the estimated number of values in the population
+    pub fn count(&self) -> f64 {
+        self.sketch.count() as f64 * self.scale
+    }
+
+    /// Return the estimated number of values in the population between `low` and `high`,
+    /// inclusive, with the same semantics as `DDSketch::count_between`
+    pub fn count_between(&self, low: f64, high: f64) -> Option<f64> {
+        self.sketch
+            .count_between(low, high)
+            .map(|n| n as f64 * self.scale)
+    }
+
+    /// Returns the estimated sum of the values in the population, or None if the sketch is empty
+    pub fn sum(&self) -> Option<f64> {
+        self.sketch.sum().map(|sum| sum * self.scale)
+    }
+
+    /// Returns the minimum value seen in the sample, or None if the sketch is empty
+    pub fn min(&self) -> Option<f64> {
+        self.sketch.min()
+    }
+
+    /// Returns the maximum value seen in the sample, or None if the sketch is empty
+    pub fn max(&self) -> Option<f64> {
+        self.sketch.max()
+    }
+
+    /// Returns the factor counts are multiplied by
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+}
+
+impl DDSketch {
+    /// Return a view of this sketch as an estimate of the population it was sampled from, with
+    /// counts multiplied by `scale`. For a stream sampled at rate `r`, `scale` is `1.0 / r`.
+    /// Panics if `scale` is not positive and finite.
+    pub fn with_scaled_count(&self, scale: f64) -> ScaledView<'_> {
+        assert!(
+            scale > 0.0 && scale.is_finite(),
+            "count scale must be positive and finite"
+        );
+
+        ScaledView {
+            sketch: self,
+            scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Config, DDSketch, SketchView};
+
+    #[test]
+    fn test_view_matches_merge() {
+        // Wide enough that merging never needs to collapse bins
+        let c = Config::new(0.01, 8192, 1.0e-9);
+        let mut d1 = DDSketch::new(c);
+        let mut d2 = DDSketch::new(c);
+        let mut d3 = DDSketch::new(c);
+        let empty = DDSketch::new(c);
+
+        for i in 1..101 {
+            d1.add(i as f64);
+            d2.add(-(i as f64) * 3.0);
+            d3.add((i * i) as f64);
+        }
+
+        let sketches = [&d1, &empty, &d2, &d3];
+        let view = SketchView::new(&sketches).unwrap();
+
+        let mut merged = DDSketch::new(c);
+        for s in &sketches {
+            merged.merge(s).unwrap();
+        }
+
+        assert_eq!(view.count(), merged.count());
+        assert_eq!(view.min(), merged.min());
+        assert_eq!(view.max(), merged.max());
+        assert_eq!(view.sum(), merged.sum());
+
+        for q in &[0.0, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0] {
+            assert_eq!(view.quantile(*q).unwrap(), merged.quantile(*q).unwrap());
+        }
+        for v in &[-1000.0, -150.0, -3.0, 0.0, 1.0, 50.0, 999.0, 20000.0] {
+            assert_eq!(view.cdf(*v), merged.cdf(*v));
+        }
+
+        assert!(view.quantile(1.01).is_err());
+    }
+
+    #[test]
+    fn test_view_empty() {
+        let c = Config::defaults();
+        let d = DDSketch::new(c);
+
+        let view = SketchView::new(&[]).unwrap();
+        assert_eq!(view.count(), 0);
+        assert_eq!(view.quantile(0.5).unwrap(), None);
+
+        let sketches = [&d];
+        let view = SketchView::new(&sketches).unwrap();
+        assert_eq!(view.min(), None);
+        assert_eq!(view.sum(), None);
+        assert_eq!(view.cdf(1.0), None);
+    }
+
+    #[test]
+    fn test_view_incompatible() {
+        let d1 = DDSketch::new(Config::defaults());
+        let d2 = DDSketch::new(Config::new(0.02, 2048, 1.0e-9));
+
+        let sketches = [&d1, &d2];
+        assert!(SketchView::new(&sketches).is_err());
+    }
+
+    #[test]
+    fn test_scaled_count() {
+        let mut dd = DDSketch::new(Config::defaults());
+        for i in 1..=100 {
+            dd.add(i as f64);
+        }
+
+        // A 10% sample of the population
+        let view = dd.with_scaled_count(10.0);
+        assert_eq!(view.count(), 1000.0);
+        assert_eq!(view.sum(), Some(50500.0));
+        assert_eq!(view.count_between(1.0, 10.0), Some(100.0));
+        assert_eq!(view.min(), dd.min());
+        assert_eq!(view.max(), dd.max());
+        for v in &[0.0, 25.0, 50.0, 99.0, 1000.0] {
+            assert_eq!(view.cdf(*v), dd.cdf(*v));
+        }
+        for q in &[0.0, 0.5, 0.99, 1.0] {
+            assert_eq!(view.quantile(*q).unwrap(), dd.quantile(*q).unwrap());
+        }
+
+        let empty = DDSketch::new(Config::defaults());
+        assert_eq!(empty.with_scaled_count(4.0).count(), 0.0);
+        assert_eq!(empty.with_scaled_count(4.0).sum(), None);
+    }
+}
@@ -1,5 +1,4 @@
 use std::cmp::Ordering;
-use std::f64::NAN;
 
 pub struct Dataset {
     values: Vec<f64>,
@@ -11,11 +10,11 @@ fn cmp_f64(a: &f64, b: &f64) -> Ordering {
     assert!(!a.is_nan() && !b.is_nan());
 
     if a < b {
-        return Ordering::Less;
+        Ordering::Less
     } else if a > b {
-        return Ordering::Greater;
+        Ordering::Greater
     } else {
-        return Ordering::Equal;
+        Ordering::Equal
     }
 }
 
@@ -41,8 +40,8 @@ impl Dataset {
     */
 
     pub fn lower_quantile(&mut self, q: f64) -> f64 {
-        if q < 0.0 || q > 1.0 || self.values.len() == 0 {
-            return NAN;
+        if !(0.0..=1.0).contains(&q) || self.values.is_empty() {
+            return f64::NAN;
         }
 
         self.sort();
@@ -52,8 +51,8 @@ impl Dataset {
     }
 
     pub fn upper_quantile(&mut self, q: f64) -> f64 {
-        if q < 0.0 || q > 1.0 || self.values.len() == 0 {
-            return NAN;
+        if !(0.0..=1.0).contains(&q) || self.values.is_empty() {
+            return f64::NAN;
         }
 
         self.sort();
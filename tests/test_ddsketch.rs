@@ -85,19 +85,17 @@ fn compare_sketches(d: &mut Dataset, g: &DDSketch) {
         let lower = d.lower_quantile(*q);
         let upper = d.upper_quantile(*q);
 
-        let min_expected;
-        if lower < 0.0 {
-            min_expected = lower * (1.0 + TEST_ALPHA);
+        let min_expected = if lower < 0.0 {
+            lower * (1.0 + TEST_ALPHA)
         } else {
-            min_expected = lower * (1.0 - TEST_ALPHA);
-        }
+            lower * (1.0 - TEST_ALPHA)
+        };
 
-        let max_expected;
-        if upper > 0.0 {
-            max_expected = upper * (1.0 + TEST_ALPHA);
+        let max_expected = if upper > 0.0 {
+            upper * (1.0 + TEST_ALPHA)
         } else {
-            max_expected = upper * (1.0 - TEST_ALPHA);
-        }
+            upper * (1.0 - TEST_ALPHA)
+        };
 
         let quantile = g.quantile(*q).unwrap().unwrap();
 